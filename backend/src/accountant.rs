@@ -0,0 +1,575 @@
+use crate::database::ledger::Ledger;
+use crate::database::schema::{PrivacyBudget, Table};
+use serde::{Deserialize, Serialize};
+
+/*
+The accountant turns the releases recorded in the ledger into the (epsilon, delta) a table
+has spent. Every composition bound below is valid on its own, the accountant reports the
+one with the smaller epsilon among the selected one and basic composition that the allowance
+covers, epsilon and delta both.
+
+- Basic: epsilons and deltas add up.
+- Advanced (Dwork, Rothblum, Vadhan 2010): k eps-DP releases are
+  (sqrt(2 k ln(1 / delta')) eps + k eps (e^eps - 1), delta')-DP. The theorem needs eps fixed
+  before the first release, with epsilons the analysts pick as they go it is no bound at
+  all. The data owner therefore fixes eps, a pure release of at most eps counts as one of
+  them, any other release falls back to basic composition.
+- zCDP (Bun, Steinke 2016): an eps-DP release is eps^2 / 2-zCDP, a Gaussian release with
+  sigma calibrated to sensitivity D is D^2 / (2 sigma^2)-zCDP, the rhos add up and
+  rho-zCDP implies (rho + 2 sqrt(rho ln(1 / delta)), delta)-DP.
+- Renyi DP (Mironov 2017): at order alpha an eps-DP release is min(alpha eps^2 / 2, eps)-RDP
+  and a rho-zCDP Gaussian release alpha rho-RDP, they add up per order and are converted
+  with Canonne, Kamath, Steinke 2020 at the best order.
+
+Releases with a delta that are not Gaussian, e.g. partition selection, have no zCDP or RDP
+guarantee and are added with basic composition on top.
+*/
+
+/// Orders the Renyi DP conversion is evaluated at.
+const RENYI_ORDERS: [f64; 18] = [
+    1.25, 1.5, 1.75, 2.0, 2.5, 3.0, 4.0, 5.0, 6.0, 8.0, 10.0, 12.0, 16.0, 20.0, 32.0, 64.0, 128.0,
+    256.0,
+];
+
+/// A single noised release as the accountant sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Release {
+    pub epsilon: f64,
+    pub delta: f64,
+    /// The zCDP parameter of a Gaussian release.
+    pub rho: Option<f64>,
+}
+
+impl Release {
+    pub fn new(cost: PrivacyBudget, rho: Option<f64>) -> Self {
+        Release {
+            epsilon: cost.epsilon,
+            delta: cost.delta,
+            rho,
+        }
+    }
+
+    pub fn cost(&self) -> PrivacyBudget {
+        PrivacyBudget::new(self.epsilon, self.delta)
+    }
+
+    fn is_pure(&self) -> bool {
+        self.rho.is_none() && self.delta == 0.0
+    }
+
+    /// The zCDP parameter, `None` for releases without a zCDP guarantee.
+    fn zcdp(&self) -> Option<f64> {
+        match self.rho {
+            Some(rho) => Some(rho),
+            None if self.is_pure() => Some(self.epsilon.powi(2) / 2.0),
+            None => None,
+        }
+    }
+
+    /// The Renyi DP epsilon at order `alpha`, `None` for releases without an RDP guarantee.
+    fn renyi(&self, alpha: f64) -> Option<f64> {
+        match self.rho {
+            Some(rho) => Some(alpha * rho),
+            None if self.is_pure() => Some((alpha * self.epsilon.powi(2) / 2.0).min(self.epsilon)),
+            None => None,
+        }
+    }
+}
+
+/// How the releases against a table are composed.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Composition {
+    #[default]
+    Basic,
+    /// The advanced composition theorem for releases of at most `epsilon` each, `delta` is
+    /// the delta' it adds.
+    Advanced { delta: f64, epsilon: f64 },
+    /// Zero-concentrated DP, converted to (epsilon, delta)-DP at `delta`.
+    Zcdp { delta: f64 },
+    /// Renyi DP, converted to (epsilon, delta)-DP at `delta`.
+    Renyi { delta: f64 },
+}
+
+fn basic(releases: &[Release]) -> PrivacyBudget {
+    releases
+        .iter()
+        .fold(PrivacyBudget::default(), |spent, release| {
+            spent + release.cost()
+        })
+}
+
+/// `None` unless every release is pure with at most `epsilon`.
+fn advanced(releases: &[Release], delta: f64, epsilon: f64) -> Option<PrivacyBudget> {
+    if !releases
+        .iter()
+        .all(|release| release.is_pure() && release.epsilon <= epsilon)
+    {
+        return None;
+    }
+    let k = releases.len() as f64;
+    Some(PrivacyBudget::new(
+        (2.0 * k * (1.0 / delta).ln()).sqrt() * epsilon + k * epsilon * epsilon.exp_m1(),
+        delta,
+    ))
+}
+
+fn zcdp(releases: &[Release], delta: f64) -> PrivacyBudget {
+    let rho: f64 = releases.iter().filter_map(Release::zcdp).sum();
+    let others: Vec<Release> = releases
+        .iter()
+        .filter(|release| release.zcdp().is_none())
+        .copied()
+        .collect();
+    let epsilon = rho + 2.0 * (rho * (1.0 / delta).ln()).sqrt();
+    PrivacyBudget::new(epsilon, delta) + basic(&others)
+}
+
+fn renyi(releases: &[Release], delta: f64) -> PrivacyBudget {
+    let epsilon = RENYI_ORDERS
+        .iter()
+        .map(|&alpha| {
+            let rdp: f64 = releases
+                .iter()
+                .filter_map(|release| release.renyi(alpha))
+                .sum();
+            rdp + ((alpha - 1.0) / alpha).ln() - (delta.ln() + alpha.ln()) / (alpha - 1.0)
+        })
+        .fold(f64::INFINITY, f64::min)
+        .max(0.0);
+    let others: Vec<Release> = releases
+        .iter()
+        .filter(|release| release.renyi(2.0).is_none())
+        .copied()
+        .collect();
+    PrivacyBudget::new(epsilon, delta) + basic(&others)
+}
+
+impl Composition {
+    /// The (epsilon, delta) spent by `releases` together.
+    ///
+    /// # Parameters
+    /// - `releases`: The releases to compose.
+    /// - `allowance`: The budget they are held against, a bound is only picked over basic
+    ///   composition if `allowance` covers its epsilon and its delta.
+    pub fn compose(&self, releases: &[Release], allowance: &PrivacyBudget) -> PrivacyBudget {
+        let basic = basic(releases);
+        if releases.is_empty() {
+            return basic;
+        }
+        let composed = match *self {
+            Composition::Basic => return basic,
+            Composition::Advanced { delta, epsilon } => match advanced(releases, delta, epsilon) {
+                Some(composed) => composed,
+                None => return basic,
+            },
+            Composition::Zcdp { delta } => zcdp(releases, delta),
+            Composition::Renyi { delta } => renyi(releases, delta),
+        };
+        match (allowance.covers(&basic), allowance.covers(&composed)) {
+            (false, true) => composed,
+            // Basic composition also spends less delta, it wins ties.
+            (true, true) if composed.epsilon < basic.epsilon => composed,
+            _ => basic,
+        }
+    }
+
+    /// Checks that the composition can be used.
+    ///
+    /// # Errors
+    /// Returns an error unless the delta of the conversion lies between 0 and 1 (exclusive)
+    /// and the epsilon of advanced composition is positive and finite.
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Composition::Basic => Ok(()),
+            Composition::Advanced { epsilon, .. } if !(epsilon > 0.0 && epsilon.is_finite()) => {
+                Err("The epsilon of advanced composition must be positive and finite".to_string())
+            }
+            Composition::Advanced { delta, .. }
+            | Composition::Zcdp { delta }
+            | Composition::Renyi { delta } => {
+                if delta > 0.0 && delta < 1.0 {
+                    Ok(())
+                } else {
+                    Err(
+                        "The delta of a composition must be between 0 and 1 (exclusive)"
+                            .to_string(),
+                    )
+                }
+            }
+        }
+    }
+}
+
+/// What an analyst was given and spent on a table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Spending {
+    pub analyst: String,
+    pub table: String,
+    pub allowance: PrivacyBudget,
+    pub spent: PrivacyBudget,
+}
+
+/// Tracks what every table has spent, following the releases recorded in the ledger.
+#[derive(Debug, Clone, Default)]
+pub struct Accountant {
+    pub composition: Composition,
+}
+
+impl Accountant {
+    pub fn new(composition: Composition) -> Self {
+        Accountant { composition }
+    }
+
+    /// The (epsilon, delta) spent on `table` so far.
+    pub fn spent(
+        &self,
+        ledger: &Ledger,
+        fingerprint: &str,
+        table: &str,
+    ) -> Result<PrivacyBudget, String> {
+        let allowance = ledger.allowance(fingerprint, table)?.unwrap_or_default();
+        Ok(self
+            .composition
+            .compose(&ledger.releases(fingerprint, table)?, &allowance))
+    }
+
+    /// The allowance of `table` minus what was spent on it.
+    pub fn remaining(
+        &self,
+        ledger: &Ledger,
+        fingerprint: &str,
+        table: &str,
+    ) -> Result<PrivacyBudget, String> {
+        let mut remaining = ledger.allowance(fingerprint, table)?.unwrap_or_default();
+        remaining.spend(&self.spent(ledger, fingerprint, table)?);
+        Ok(remaining)
+    }
+
+    /// The (epsilon, delta) `analyst` spent on `table` so far.
+    pub fn analyst_spent(
+        &self,
+        ledger: &Ledger,
+        fingerprint: &str,
+        analyst: &str,
+        table: &str,
+    ) -> Result<PrivacyBudget, String> {
+        let allowance = self.analyst_allowance(ledger, fingerprint, analyst, table)?;
+        Ok(self.composition.compose(
+            &ledger.analyst_releases(fingerprint, analyst, table)?,
+            &allowance,
+        ))
+    }
+
+    /// The allowance of `analyst` on `table`, nothing if the data owner set none for them.
+    ///
+    /// Analyst names are labels the client picks, falling back to the allowance of the
+    /// table would let a capped analyst lift their cap by querying under a new name.
+    pub fn analyst_allowance(
+        &self,
+        ledger: &Ledger,
+        fingerprint: &str,
+        analyst: &str,
+        table: &str,
+    ) -> Result<PrivacyBudget, String> {
+        Ok(ledger
+            .analyst_allowance(fingerprint, analyst, table)?
+            .unwrap_or_default())
+    }
+
+    /// What `analyst` can still spend on `table`, their own allowance minus what they spent,
+    /// capped by what is left of the allowance of the table.
+    pub fn analyst_remaining(
+        &self,
+        ledger: &Ledger,
+        fingerprint: &str,
+        analyst: &str,
+        table: &str,
+    ) -> Result<PrivacyBudget, String> {
+        let mut remaining = self.analyst_allowance(ledger, fingerprint, analyst, table)?;
+        remaining.spend(&self.analyst_spent(ledger, fingerprint, analyst, table)?);
+        let table_remaining = self.remaining(ledger, fingerprint, table)?;
+        Ok(PrivacyBudget::new(
+            remaining.epsilon.min(table_remaining.epsilon),
+            remaining.delta.min(table_remaining.delta),
+        ))
+    }
+
+    /// Whether `analyst` can run `releases` against `table`, their own allowance and the
+    /// allowance of the table both have to cover them on top of what was spent.
+    pub fn admits(
+        &self,
+        ledger: &Ledger,
+        fingerprint: &str,
+        analyst: &str,
+        table: &str,
+        releases: &[Release],
+    ) -> Result<bool, String> {
+        let allowance = ledger.allowance(fingerprint, table)?.unwrap_or_default();
+        let mut history = ledger.releases(fingerprint, table)?;
+        history.extend_from_slice(releases);
+        if !allowance.covers(&self.composition.compose(&history, &allowance)) {
+            return Ok(false);
+        }
+        let allowance = self.analyst_allowance(ledger, fingerprint, analyst, table)?;
+        let mut history = ledger.analyst_releases(fingerprint, analyst, table)?;
+        history.extend_from_slice(releases);
+        Ok(allowance.covers(&self.composition.compose(&history, &allowance)))
+    }
+
+    /// What every analyst was given and spent on every table in `tables`.
+    pub fn spending(
+        &self,
+        ledger: &Ledger,
+        fingerprint: &str,
+        tables: &[Table],
+    ) -> Result<Vec<Spending>, String> {
+        let mut spending = vec![];
+        for analyst in ledger.analysts(fingerprint)? {
+            for table in tables.iter() {
+                spending.push(Spending {
+                    allowance: self.analyst_allowance(
+                        ledger,
+                        fingerprint,
+                        &analyst,
+                        &table.name,
+                    )?,
+                    spent: self.analyst_spent(ledger, fingerprint, &analyst, &table.name)?,
+                    analyst: analyst.to_owned(),
+                    table: table.name.to_owned(),
+                });
+            }
+        }
+        Ok(spending)
+    }
+
+    /// Sets the `privacy_budget` of every table to what is left of it.
+    pub fn load_budgets(
+        &self,
+        ledger: &Ledger,
+        fingerprint: &str,
+        tables: &mut [Table],
+    ) -> Result<(), String> {
+        for table in tables.iter_mut() {
+            table.privacy_budget = self.remaining(ledger, fingerprint, &table.name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Accountant, Composition, Release};
+    use crate::database::ledger::Ledger;
+    use crate::database::schema::PrivacyBudget;
+
+    const ALLOWANCE: PrivacyBudget = PrivacyBudget {
+        epsilon: 10.0,
+        delta: 1e-5,
+    };
+
+    fn pure(epsilon: f64, count: usize) -> Vec<Release> {
+        vec![Release::new(PrivacyBudget::new(epsilon, 0.0), None); count]
+    }
+
+    #[test]
+    fn basic_composition() {
+        let spent = Composition::Basic.compose(&pure(0.5, 4), &ALLOWANCE);
+        assert_eq!(PrivacyBudget::new(2.0, 0.0), spent);
+        assert_eq!(
+            PrivacyBudget::default(),
+            Composition::Basic.compose(&[], &ALLOWANCE)
+        );
+    }
+
+    #[test]
+    fn remaining_budget() {
+        let accountant = Accountant::default();
+        let mut ledger = Ledger::in_memory().unwrap();
+        assert_eq!(
+            PrivacyBudget::default(),
+            accountant.remaining(&ledger, "a", "users").unwrap()
+        );
+
+        ledger
+            .set_allowance("a", "users", &PrivacyBudget::new(10.0, 1e-5))
+            .unwrap();
+        for (fingerprint, analyst, release) in
+            [("a", "alice", 2.0), ("a", "bob", 1.5), ("b", "alice", 4.0)]
+        {
+            ledger
+                .charge(fingerprint, "users", analyst, &pure(release, 1)[0])
+                .unwrap();
+        }
+        assert_eq!(
+            PrivacyBudget::new(6.5, 1e-5),
+            accountant.remaining(&ledger, "a", "users").unwrap()
+        );
+        ledger
+            .set_analyst_allowance("a", "alice", "users", &PrivacyBudget::new(10.0, 0.0))
+            .unwrap();
+        ledger
+            .set_analyst_allowance("a", "bob", "users", &PrivacyBudget::new(2.0, 0.0))
+            .unwrap();
+        // Alice is capped by the table, Bob by his own allowance.
+        assert_eq!(
+            6.5,
+            accountant
+                .analyst_remaining(&ledger, "a", "alice", "users")
+                .unwrap()
+                .epsilon
+        );
+        assert_eq!(
+            0.5,
+            accountant
+                .analyst_remaining(&ledger, "a", "bob", "users")
+                .unwrap()
+                .epsilon
+        );
+        assert!(accountant
+            .admits(&ledger, "a", "alice", "users", &pure(6.5, 1))
+            .unwrap());
+        assert!(!accountant
+            .admits(&ledger, "a", "alice", "users", &pure(6.6, 1))
+            .unwrap());
+        assert!(!accountant
+            .admits(&ledger, "a", "bob", "users", &pure(1.0, 1))
+            .unwrap());
+        // Carol has no allowance of her own and cannot spend anything.
+        assert_eq!(
+            PrivacyBudget::default(),
+            accountant
+                .analyst_remaining(&ledger, "a", "carol", "users")
+                .unwrap()
+        );
+        assert!(!accountant
+            .admits(&ledger, "a", "carol", "users", &pure(0.1, 1))
+            .unwrap());
+
+        // A new allowance does not give spent budget back.
+        ledger
+            .set_allowance("a", "users", &PrivacyBudget::new(5.0, 1e-5))
+            .unwrap();
+        assert_eq!(
+            1.5,
+            accountant.remaining(&ledger, "a", "users").unwrap().epsilon
+        );
+    }
+
+    #[test]
+    fn many_small_queries() {
+        let releases = pure(0.01, 1000);
+        let basic = Composition::Basic.compose(&releases, &ALLOWANCE).epsilon;
+        for composition in [
+            Composition::Advanced {
+                delta: 1e-6,
+                epsilon: 0.01,
+            },
+            Composition::Zcdp { delta: 1e-6 },
+            Composition::Renyi { delta: 1e-6 },
+        ] {
+            let spent = composition.compose(&releases, &ALLOWANCE);
+            assert!(spent.epsilon < basic / 2.0, "{composition:?}: {spent}");
+            assert_eq!(1e-6, spent.delta);
+        }
+    }
+
+    #[test]
+    fn never_worse_than_basic() {
+        let releases = pure(1.0, 2);
+        assert_eq!(
+            Composition::Basic.compose(&releases, &ALLOWANCE),
+            Composition::Advanced {
+                delta: 1e-6,
+                epsilon: 1.0
+            }
+            .compose(&releases, &ALLOWANCE)
+        );
+    }
+
+    #[test]
+    fn advanced_needs_a_fixed_epsilon() {
+        let advanced = Composition::Advanced {
+            delta: 1e-6,
+            epsilon: 0.01,
+        };
+        let basic = |releases: &[Release]| Composition::Basic.compose(releases, &ALLOWANCE);
+        // Smaller releases count as releases of the fixed epsilon.
+        let mut releases = pure(0.005, 500);
+        releases.extend(pure(0.01, 500));
+        assert_eq!(
+            advanced.compose(&pure(0.01, 1000), &ALLOWANCE),
+            advanced.compose(&releases, &ALLOWANCE)
+        );
+        // A larger or a Gaussian release leaves basic composition only.
+        releases.push(pure(0.02, 1)[0]);
+        assert_eq!(basic(&releases), advanced.compose(&releases, &ALLOWANCE));
+        releases.pop();
+        releases.push(Release::new(PrivacyBudget::new(0.01, 1e-7), Some(5e-5)));
+        assert_eq!(basic(&releases), advanced.compose(&releases, &ALLOWANCE));
+
+        assert!(advanced.validate().is_ok());
+        for epsilon in [0.0, -1.0, f64::INFINITY, f64::NAN] {
+            let composition = Composition::Advanced {
+                delta: 1e-6,
+                epsilon,
+            };
+            assert!(composition.validate().is_err(), "{epsilon}");
+        }
+    }
+
+    #[test]
+    fn allowance_covers_epsilon_and_delta() {
+        let releases = pure(0.01, 1000);
+        for composition in [
+            Composition::Advanced {
+                delta: 1e-6,
+                epsilon: 0.01,
+            },
+            Composition::Zcdp { delta: 1e-6 },
+            Composition::Renyi { delta: 1e-6 },
+        ] {
+            // Without a delta to spend the tighter epsilon does not count.
+            let spent = composition.compose(&releases, &PrivacyBudget::new(10.0, 0.0));
+            assert!(
+                (spent.epsilon - 10.0).abs() < 1e-9,
+                "{composition:?}: {spent}"
+            );
+            assert_eq!(0.0, spent.delta);
+            assert!(composition.compose(&releases, &ALLOWANCE).epsilon < 5.0);
+
+            let accountant = Accountant::new(composition);
+            let mut ledger = Ledger::in_memory().unwrap();
+            for budget in [PrivacyBudget::new(5.0, 0.0), PrivacyBudget::new(5.0, 1e-5)] {
+                ledger.set_allowance("a", "users", &budget).unwrap();
+                ledger
+                    .set_analyst_allowance("a", "alice", "users", &budget)
+                    .unwrap();
+                assert_eq!(
+                    budget.delta > 0.0,
+                    accountant
+                        .admits(&ledger, "a", "alice", "users", &releases)
+                        .unwrap(),
+                    "{composition:?} within {budget}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn gaussian_releases() {
+        // Gaussian releases with rho = 0.005 each.
+        let gaussian = Release::new(PrivacyBudget::new(0.5, 1e-5), Some(0.005));
+        let selection = Release::new(PrivacyBudget::new(0.1, 1e-7), None);
+        let mut releases = vec![gaussian; 100];
+        releases.push(selection);
+        let spent = Composition::Zcdp { delta: 1e-6 }.compose(&releases, &ALLOWANCE);
+        // rho = 0.5, epsilon = 0.5 + 2 sqrt(0.5 ln(1e6)) plus the selection.
+        let expected = 0.5 + 2.0 * (0.5 * 1e6_f64.ln()).sqrt() + 0.1;
+        assert!((spent.epsilon - expected).abs() < 1e-9);
+        assert!((spent.delta - (1e-6 + 1e-7)).abs() < 1e-15);
+
+        let renyi = Composition::Renyi { delta: 1e-6 }.compose(&releases, &ALLOWANCE);
+        assert!(renyi.epsilon <= spent.epsilon);
+    }
+}
@@ -0,0 +1,541 @@
+use crate::database::database::SupportedDatabases;
+use crate::database::identifiers::IdentifierRules;
+use crate::database::schema::{ColumnBounds, PrivacyBudget, PrivacyUnit, Table};
+use crate::query::aggregations::{parse_function, AggregationPolicy, DEFAULT_AGGREGATIONS};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Environment variable overriding where the configuration is read from.
+pub const CONFIG_VARIABLE: &str = "DIFFPRIV_CONFIG";
+
+/// The key holding the budget of a table among its columns.
+pub const TABLE_PRIVACY_KEY: &str = "__table__privacy";
+
+/// The key holding the aggregates allowed on a table among its columns.
+pub const TABLE_AGGREGATIONS_KEY: &str = "__table__aggregations";
+
+/// The key holding the privacy unit of a table among its columns.
+pub const TABLE_PRIVACY_UNIT_KEY: &str = "__table__privacy_unit";
+
+/// The privacy configuration of the data owner, as stored in `conf.json`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The aggregates allowed on every column without a list of its own, written as
+    /// `avg(` or `avg`. Every supported aggregate if left out.
+    #[serde(default)]
+    pub allowed_aggregations: Vec<String>,
+    pub mysql: Option<BackendConfig>,
+    pub sqlite: Option<BackendConfig>,
+}
+
+/// Where a backend's data lives and how its tables are protected.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackendConfig {
+    #[serde(default)]
+    pub in_memory: bool,
+    pub uri: Option<String>,
+    pub database: Option<String>,
+    #[serde(default)]
+    pub tables: HashMap<String, TableConfig>,
+}
+
+/// The budget of a table, either an epsilon or a full (epsilon, delta) budget.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum TablePrivacy {
+    Epsilon(f64),
+    Budget(PrivacyBudget),
+}
+
+impl TablePrivacy {
+    pub fn budget(&self) -> PrivacyBudget {
+        match *self {
+            TablePrivacy::Epsilon(epsilon) => PrivacyBudget::new(epsilon, 0.0),
+            TablePrivacy::Budget(budget) => budget,
+        }
+    }
+}
+
+/// The privacy unit of a table, either its column or the column and how much a unit may
+/// contribute.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum PrivacyUnitConfig {
+    Column(String),
+    Detailed(PrivacyUnit),
+}
+
+impl PrivacyUnitConfig {
+    pub fn unit(&self) -> PrivacyUnit {
+        match self {
+            PrivacyUnitConfig::Column(column) => PrivacyUnit::new(column),
+            PrivacyUnitConfig::Detailed(unit) => unit.clone(),
+        }
+    }
+}
+
+/// The settings of a column written out as an object.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnSettings {
+    pub sensitivity: f64,
+    /// The aggregates allowed on the column, overriding those of its table.
+    pub aggregations: Option<Vec<String>>,
+    /// The range of the column's values, needed by every aggregate but COUNT.
+    /// SUM clamps into it and takes its sensitivity from it.
+    pub bounds: Option<ColumnBounds>,
+}
+
+/// A column, either its sensitivity or an object with its sensitivity, the aggregates
+/// allowed on it and its bounds.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ColumnConfig {
+    Sensitivity(f64),
+    Detailed(ColumnSettings),
+}
+
+impl ColumnConfig {
+    pub fn sensitivity(&self) -> f64 {
+        match self {
+            ColumnConfig::Sensitivity(sensitivity) => *sensitivity,
+            ColumnConfig::Detailed(settings) => settings.sensitivity,
+        }
+    }
+
+    pub fn aggregations(&self) -> Option<&[String]> {
+        match self {
+            ColumnConfig::Sensitivity(_) => None,
+            ColumnConfig::Detailed(settings) => settings.aggregations.as_deref(),
+        }
+    }
+
+    pub fn bounds(&self) -> Option<ColumnBounds> {
+        match self {
+            ColumnConfig::Sensitivity(_) => None,
+            ColumnConfig::Detailed(settings) => settings.bounds,
+        }
+    }
+}
+
+/// The columns of a table, its budget under `__table__privacy`, the aggregates allowed
+/// on it under `__table__aggregations` and its privacy unit under `__table__privacy_unit`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct TableConfig {
+    #[serde(rename = "__table__privacy")]
+    pub privacy: Option<TablePrivacy>,
+    #[serde(rename = "__table__aggregations")]
+    pub aggregations: Option<Vec<String>>,
+    #[serde(rename = "__table__privacy_unit")]
+    pub privacy_unit: Option<PrivacyUnitConfig>,
+    #[serde(flatten)]
+    pub columns: HashMap<String, ColumnConfig>,
+}
+
+/// What applying a configuration to a schema found.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConfigReport {
+    /// Tables of the schema the configuration does not mention.
+    pub missing_tables: Vec<String>,
+    /// Tables of the configuration the schema does not have.
+    pub unknown_tables: Vec<String>,
+    /// Columns, as `table.column`, of configured tables without a sensitivity.
+    pub missing_columns: Vec<String>,
+    /// Columns, as `table.column`, of the configuration the schema does not have.
+    pub unknown_columns: Vec<String>,
+    /// The budget of every configured table, keyed by its name in the schema.
+    pub budgets: HashMap<String, PrivacyBudget>,
+}
+
+impl ConfigReport {
+    /// Whether the configuration and the schema describe the same tables and columns.
+    pub fn is_complete(&self) -> bool {
+        self.missing_tables.is_empty()
+            && self.unknown_tables.is_empty()
+            && self.missing_columns.is_empty()
+            && self.unknown_columns.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sections = [
+            ("missing tables", &self.missing_tables),
+            ("unknown tables", &self.unknown_tables),
+            ("missing columns", &self.missing_columns),
+            ("unknown columns", &self.unknown_columns),
+        ];
+        let found: Vec<String> = sections
+            .iter()
+            .filter(|(_, names)| !names.is_empty())
+            .map(|(section, names)| format!("{section}: {}", names.join(", ")))
+            .collect();
+        if found.is_empty() {
+            write!(f, "The configuration covers every table and column")
+        } else {
+            write!(f, "{}", found.join("; "))
+        }
+    }
+}
+
+impl Config {
+    /// `DIFFPRIV_CONFIG` if set, otherwise `conf.json` in the working directory.
+    pub fn default_path() -> PathBuf {
+        std::env::var(CONFIG_VARIABLE)
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from("conf.json"))
+    }
+
+    /// Reads and parses the configuration at `path`.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+        Config::parse(&contents)
+    }
+
+    /// Parses a configuration.
+    ///
+    /// # Errors
+    /// Returns an error if `contents` is not a valid configuration, e.g. has a key the
+    /// configuration does not know or a negative sensitivity.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let config: Config =
+            serde_json::from_str(contents).map_err(|e| format!("Invalid configuration: {e}"))?;
+        for function in config.allowed_aggregations.iter() {
+            parse_function(function)?;
+        }
+        for (flavour, backend) in [("mysql", &config.mysql), ("sqlite", &config.sqlite)] {
+            let Some(backend) = backend else { continue };
+            for (table, table_config) in backend.tables.iter() {
+                table_config.validate(&format!("{flavour}.{table}"))?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// The aggregates allowed on the columns of `flavour`'s tables, matched following
+    /// `identifiers`.
+    pub fn aggregation_policy(
+        &self,
+        flavour: SupportedDatabases,
+        identifiers: IdentifierRules,
+    ) -> Result<AggregationPolicy, String> {
+        let default: Vec<String> = if self.allowed_aggregations.is_empty() {
+            DEFAULT_AGGREGATIONS.iter().map(|f| f.to_string()).collect()
+        } else {
+            self.allowed_aggregations.clone()
+        };
+        let mut policy = AggregationPolicy::new(&default, identifiers)?;
+        let Some(backend) = self.backend(flavour) else {
+            return Ok(policy);
+        };
+        for (table, table_config) in backend.tables.iter() {
+            if let Some(functions) = &table_config.aggregations {
+                policy.restrict_table(table, functions)?;
+            }
+            for (column, column_config) in table_config.columns.iter() {
+                if let Some(functions) = column_config.aggregations() {
+                    policy.restrict_column(table, column, functions)?;
+                }
+            }
+        }
+        Ok(policy)
+    }
+
+    /// The configuration of `flavour`.
+    pub fn backend(&self, flavour: SupportedDatabases) -> Option<&BackendConfig> {
+        match flavour {
+            SupportedDatabases::MySQL => self.mysql.as_ref(),
+            SupportedDatabases::SQLite => self.sqlite.as_ref(),
+        }
+    }
+}
+
+impl TableConfig {
+    fn validate(&self, name: &str) -> Result<(), String> {
+        for (column, column_config) in self.columns.iter() {
+            let sensitivity = column_config.sensitivity();
+            if !sensitivity.is_finite() || sensitivity < 0.0 {
+                return Err(format!(
+                    "The sensitivity of {name}.{column} must be a non-negative number"
+                ));
+            }
+            for function in column_config.aggregations().unwrap_or_default() {
+                parse_function(function)?;
+            }
+            if let Some(bounds) = column_config.bounds() {
+                bounds
+                    .validate()
+                    .map_err(|error| format!("{error} on {name}.{column}"))?;
+            }
+        }
+        for function in self.aggregations.iter().flatten() {
+            parse_function(function)?;
+        }
+        if let Some(privacy_unit) = &self.privacy_unit {
+            privacy_unit.unit().validate()?;
+        }
+        if let Some(privacy) = &self.privacy {
+            let budget = privacy.budget();
+            if !budget.epsilon.is_finite()
+                || budget.epsilon < 0.0
+                || !(0.0..1.0).contains(&budget.delta)
+            {
+                return Err(format!(
+                    "The {TABLE_PRIVACY_KEY} of {name} needs a non-negative epsilon and a delta below 1"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BackendConfig {
+    /// Sets the sensitivity and the bounds of every configured column of `tables` and the
+    /// privacy unit of every configured table.
+    ///
+    /// Bounds and privacy units the configuration does not give are left as they are, e.g.
+    /// as the data owner saved them in the ledger.
+    ///
+    /// Tables and columns are matched following `identifiers`, a configuration written for
+    /// `Users` applies to a SQLite table `users`.
+    ///
+    /// # Returns
+    /// What is missing on either side and the budgets of the configured tables.
+    pub fn apply(&self, tables: &mut [Table], identifiers: IdentifierRules) -> ConfigReport {
+        let mut report = ConfigReport::default();
+        let mut names: Vec<&String> = self.tables.keys().collect();
+        names.sort();
+        for name in names.iter() {
+            if !tables
+                .iter()
+                .any(|table| identifiers.same_table(&table.name, name))
+            {
+                report.unknown_tables.push(name.to_string());
+            }
+        }
+        for table in tables.iter_mut() {
+            let Some(table_config) = names
+                .iter()
+                .find(|name| identifiers.same_table(&table.name, name))
+                .map(|name| &self.tables[*name])
+            else {
+                report.missing_tables.push(table.name.to_owned());
+                continue;
+            };
+            if let Some(privacy) = &table_config.privacy {
+                report
+                    .budgets
+                    .insert(table.name.to_owned(), privacy.budget());
+            }
+            if let Some(privacy_unit) = &table_config.privacy_unit {
+                let unit = privacy_unit.unit();
+                match table
+                    .columns
+                    .iter()
+                    .find(|column| identifiers.same_column(&column.name, &unit.column))
+                {
+                    Some(column) => {
+                        table.privacy_unit = Some(PrivacyUnit {
+                            column: column.name.to_owned(),
+                            ..unit
+                        })
+                    }
+                    None => report
+                        .unknown_columns
+                        .push(format!("{}.{}", table.name, unit.column)),
+                }
+            }
+            let mut columns: Vec<(&String, &ColumnConfig)> = table_config.columns.iter().collect();
+            columns.sort_by(|a, b| a.0.cmp(b.0));
+            for (name, _) in columns.iter() {
+                if !table
+                    .columns
+                    .iter()
+                    .any(|column| identifiers.same_column(&column.name, name))
+                {
+                    report
+                        .unknown_columns
+                        .push(format!("{}.{name}", table.name));
+                }
+            }
+            for column in table.columns.iter_mut() {
+                match columns
+                    .iter()
+                    .find(|(name, _)| identifiers.same_column(&column.name, name))
+                {
+                    Some((_, column_config)) => {
+                        column.sensitivity = column_config.sensitivity();
+                        if let Some(bounds) = column_config.bounds() {
+                            column.bounds = Some(bounds);
+                        }
+                    }
+                    None => report
+                        .missing_columns
+                        .push(format!("{}.{}", table.name, column.name)),
+                }
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use crate::database::database::SupportedDatabases;
+    use crate::database::identifiers::IdentifierRules;
+    use crate::database::schema::{Column, ColumnBounds, PrivacyBudget, PrivacyUnit, Table};
+
+    fn users() -> Vec<Table> {
+        let column = |name: &str| Column {
+            name: name.to_string(),
+            ctype: "INTEGER".to_string(),
+            sensitivity: 0.0,
+            bounds: None,
+            usage: None,
+            table_name: "users".to_string(),
+        };
+        vec![
+            Table {
+                name: "users".to_string(),
+                columns: vec![column("age"), column("salary")],
+                privacy_budget: PrivacyBudget::default(),
+                privacy_unit: None,
+            },
+            Table {
+                name: "orders".to_string(),
+                columns: vec![],
+                privacy_budget: PrivacyBudget::default(),
+                privacy_unit: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn repository_config() {
+        let config = Config::parse(include_str!("../conf.json")).unwrap();
+        assert_eq!(
+            Some("test"),
+            config
+                .backend(SupportedDatabases::MySQL)
+                .and_then(|backend| backend.database.as_deref())
+        );
+        let users = &config.sqlite.unwrap().tables["Users"];
+        assert_eq!(
+            Some(0.12),
+            users.privacy.map(|privacy| privacy.budget().epsilon)
+        );
+        assert_eq!(2, users.columns.len());
+    }
+
+    #[test]
+    fn aggregations() {
+        let config = Config::parse(
+            r#"{"allowed_aggregations": ["sum(", "count("], "sqlite": {"tables": {
+                "users": {"age": {"sensitivity": 1.0, "aggregations": ["avg("]}, "salary": 1.0},
+                "orders": {"__table__aggregations": ["count("]}
+            }}}"#,
+        )
+        .unwrap();
+        let policy = config
+            .aggregation_policy(SupportedDatabases::SQLite, IdentifierRules::sqlite())
+            .unwrap();
+        assert_eq!(
+            ["avg".to_string()].as_slice(),
+            policy.allowed("Users", "AGE")
+        );
+        assert_eq!(2, policy.allowed("users", "salary").len());
+        assert_eq!(
+            ["count".to_string()].as_slice(),
+            policy.allowed("orders", "id")
+        );
+        assert_eq!(
+            1.0,
+            config.sqlite.unwrap().tables["users"].columns["age"].sensitivity()
+        );
+    }
+
+    #[test]
+    fn apply() {
+        let config = Config::parse(
+            r#"{"sqlite": {"tables": {
+                "Users": {"AGE": {"sensitivity": 2.0, "bounds": {"lower": 0, "upper": 120}}, "height": 1.0, "__table__privacy": {"epsilon": 3.0, "delta": 1e-6},
+                    "__table__privacy_unit": {"column": "Salary", "max_groups": 2}},
+                "items": {}
+            }}}"#,
+        )
+        .unwrap();
+        let mut tables = users();
+        let report = config
+            .backend(SupportedDatabases::SQLite)
+            .unwrap()
+            .apply(&mut tables, IdentifierRules::sqlite());
+        assert_eq!(2.0, tables[0].columns[0].sensitivity);
+        assert_eq!(
+            Some(ColumnBounds::new(0.0, 120.0).unwrap()),
+            tables[0].columns[0].bounds
+        );
+        assert_eq!(
+            Some(PrivacyUnit {
+                column: "salary".to_string(),
+                max_rows: 1,
+                max_groups: 2
+            }),
+            tables[0].privacy_unit
+        );
+        assert_eq!(
+            Some(&PrivacyBudget::new(3.0, 1e-6)),
+            report.budgets.get("users")
+        );
+        assert_eq!(["orders".to_string()].to_vec(), report.missing_tables);
+        assert_eq!(["items".to_string()].to_vec(), report.unknown_tables);
+        assert_eq!(
+            ["users.salary".to_string()].to_vec(),
+            report.missing_columns
+        );
+        assert_eq!(
+            ["users.height".to_string()].to_vec(),
+            report.unknown_columns
+        );
+        assert!(!report.is_complete());
+    }
+
+    #[test]
+    fn apply_keeps_what_is_not_configured() {
+        let config =
+            Config::parse(r#"{"sqlite": {"tables": {"users": {"age": 2.0, "salary": 1.0}}}}"#)
+                .unwrap();
+        let mut tables = users();
+        let bounds = ColumnBounds::new(0.0, 120.0).unwrap();
+        tables[0].columns[0].bounds = Some(bounds);
+        tables[0].privacy_unit = Some(PrivacyUnit::new("salary"));
+        config
+            .backend(SupportedDatabases::SQLite)
+            .unwrap()
+            .apply(&mut tables, IdentifierRules::sqlite());
+        assert_eq!(2.0, tables[0].columns[0].sensitivity);
+        assert_eq!(Some(bounds), tables[0].columns[0].bounds);
+        assert_eq!(Some(PrivacyUnit::new("salary")), tables[0].privacy_unit);
+    }
+
+    #[test]
+    fn invalid_config() {
+        for config in [
+            r#"{"sqlite": {"tables": {"users": {"age": -1.0}}}}"#,
+            r#"{"sqlite": {"tables": {"users": {"__table__privacy": -1.0}}}}"#,
+            r#"{"sqlite": {"tables": {"users": {"age": "high"}}}}"#,
+            r#"{"sqlite": {"tables": {"users": {"__table__privacy_unit": {"column": "id", "max_rows": 0}}}}}"#,
+            r#"{"postgres": {}}"#,
+            r#"{"allowed_aggregations": ["mode("]}"#,
+            r#"{"sqlite": {"tables": {"users": {"age": {"sensitivity": 1.0, "aggregations": ["mode("]}}}}}"#,
+            r#"{"sqlite": {"tables": {"users": {"age": {"sensitivity": 1.0, "bounds": {"lower": 9, "upper": 1}}}}}}"#,
+        ] {
+            assert!(Config::parse(config).is_err(), "{config}");
+        }
+    }
+}
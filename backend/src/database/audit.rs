@@ -0,0 +1,350 @@
+use rusqlite::{params, Connection as SqliteConnection};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable overriding where the audit log is stored.
+pub const AUDIT_LOG_VARIABLE: &str = "DIFFPRIV_AUDIT_LOG";
+
+const AUDIT_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    recorded_at INTEGER NOT NULL,
+    fingerprint TEXT NOT NULL,
+    analyst TEXT NOT NULL,
+    query TEXT NOT NULL,
+    -- JSON arrays of names.
+    tables TEXT NOT NULL,
+    mechanisms TEXT NOT NULL,
+    epsilon REAL NOT NULL,
+    delta REAL NOT NULL,
+    outcome TEXT NOT NULL,
+    message TEXT
+);
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN SELECT RAISE(ABORT, 'The audit log is append-only'); END;
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN SELECT RAISE(ABORT, 'The audit log is append-only'); END;
+";
+
+const CSV_HEADER: &str =
+    "id,recorded_at,fingerprint,analyst,query,tables,mechanisms,epsilon,delta,outcome,message";
+
+/// What became of a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    /// The query ran and its noised result was released.
+    Answered,
+    /// The query was rejected before it read any data, nothing was charged.
+    Rejected,
+    /// The query ran and was charged but failed, nothing was released.
+    Failed,
+}
+
+impl AuditOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AuditOutcome::Answered => "answered",
+            AuditOutcome::Rejected => "rejected",
+            AuditOutcome::Failed => "failed",
+        }
+    }
+
+    fn parse(outcome: &str) -> Self {
+        match outcome {
+            "answered" => AuditOutcome::Answered,
+            "rejected" => AuditOutcome::Rejected,
+            _ => AuditOutcome::Failed,
+        }
+    }
+}
+
+/// A query as the audit log records it.
+///
+/// Only what was asked and what it cost is recorded, never a true or noised value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Assigned by the log, `0` until the entry is recorded.
+    pub id: i64,
+    /// Seconds since the Unix epoch.
+    pub recorded_at: i64,
+    pub fingerprint: String,
+    pub analyst: String,
+    pub query: String,
+    pub tables: Vec<String>,
+    pub mechanisms: Vec<String>,
+    /// The epsilon charged on every table, `0` unless the query was charged.
+    pub epsilon: f64,
+    /// The delta charged on every table.
+    pub delta: f64,
+    pub outcome: AuditOutcome,
+    /// Why the query was rejected or failed.
+    pub message: Option<String>,
+}
+
+impl AuditEntry {
+    /// A rejected entry for `query`, the pipeline fills it in as the query progresses.
+    pub fn new(fingerprint: &str, analyst: &str, query: &str) -> Self {
+        AuditEntry {
+            id: 0,
+            recorded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs() as i64)
+                .unwrap_or_default(),
+            fingerprint: fingerprint.to_string(),
+            analyst: analyst.to_string(),
+            query: query.to_string(),
+            tables: vec![],
+            mechanisms: vec![],
+            epsilon: 0.0,
+            delta: 0.0,
+            outcome: AuditOutcome::Rejected,
+            message: None,
+        }
+    }
+
+    fn csv_row(&self) -> String {
+        [
+            self.id.to_string(),
+            self.recorded_at.to_string(),
+            csv_field(&self.fingerprint),
+            csv_field(&self.analyst),
+            csv_field(&self.query),
+            csv_field(&self.tables.join(";")),
+            csv_field(&self.mechanisms.join(";")),
+            self.epsilon.to_string(),
+            self.delta.to_string(),
+            self.outcome.as_str().to_string(),
+            csv_field(self.message.as_deref().unwrap_or_default()),
+        ]
+        .join(",")
+    }
+}
+
+/// Quotes `value` if it contains a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Which entries to read, every field left out matches all entries.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuditFilter {
+    pub fingerprint: Option<String>,
+    pub analyst: Option<String>,
+    /// Entries recorded at or after this many seconds since the Unix epoch.
+    pub since: Option<i64>,
+    /// Entries recorded before this many seconds since the Unix epoch.
+    pub until: Option<i64>,
+}
+
+/// The formats the audit log can be exported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+/// Append-only record of every query that was asked.
+///
+/// Entries can be added and read but never changed or removed, the database refuses
+/// updates and deletes of recorded entries.
+pub struct AuditLog {
+    connection: SqliteConnection,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let connection = SqliteConnection::open(path).map_err(|e| e.to_string())?;
+        AuditLog::from_connection(connection)
+    }
+
+    /// An audit log that is lost once dropped, for tests.
+    pub fn in_memory() -> Result<Self, String> {
+        let connection = SqliteConnection::open_in_memory().map_err(|e| e.to_string())?;
+        AuditLog::from_connection(connection)
+    }
+
+    fn from_connection(connection: SqliteConnection) -> Result<Self, String> {
+        connection
+            .execute_batch(AUDIT_SCHEMA)
+            .map_err(|e| e.to_string())?;
+        Ok(AuditLog { connection })
+    }
+
+    /// `DIFFPRIV_AUDIT_LOG` if set, otherwise `.diffpriv/audit.sqlite3` in the home directory.
+    pub fn default_path() -> PathBuf {
+        if let Ok(path) = std::env::var(AUDIT_LOG_VARIABLE) {
+            return PathBuf::from(path);
+        }
+        let home = std::env::var("HOME")
+            .or_else(|_| std::env::var("USERPROFILE"))
+            .unwrap_or_else(|_| ".".to_string());
+        Path::new(&home).join(".diffpriv").join("audit.sqlite3")
+    }
+
+    /// Appends `entry` to the log.
+    ///
+    /// # Returns
+    /// The id of the recorded entry.
+    pub fn record(&mut self, entry: &AuditEntry) -> Result<i64, String> {
+        let tables = serde_json::to_string(&entry.tables).map_err(|e| e.to_string())?;
+        let mechanisms = serde_json::to_string(&entry.mechanisms).map_err(|e| e.to_string())?;
+        self.connection
+            .execute(
+                "INSERT INTO audit_log (recorded_at, fingerprint, analyst, query, tables,
+                mechanisms, epsilon, delta, outcome, message)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    entry.recorded_at,
+                    entry.fingerprint,
+                    entry.analyst,
+                    entry.query,
+                    tables,
+                    mechanisms,
+                    entry.epsilon,
+                    entry.delta,
+                    entry.outcome.as_str(),
+                    entry.message,
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// The entries matching `filter`, oldest first.
+    pub fn entries(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>, String> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT id, recorded_at, fingerprint, analyst, query, tables, mechanisms,
+                epsilon, delta, outcome, message FROM audit_log
+                WHERE (?1 IS NULL OR fingerprint = ?1) AND (?2 IS NULL OR analyst = ?2)
+                AND (?3 IS NULL OR recorded_at >= ?3) AND (?4 IS NULL OR recorded_at < ?4)
+                ORDER BY id",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map(
+                params![
+                    filter.fingerprint,
+                    filter.analyst,
+                    filter.since,
+                    filter.until
+                ],
+                |row| {
+                    Ok((
+                        AuditEntry {
+                            id: row.get(0)?,
+                            recorded_at: row.get(1)?,
+                            fingerprint: row.get(2)?,
+                            analyst: row.get(3)?,
+                            query: row.get(4)?,
+                            tables: vec![],
+                            mechanisms: vec![],
+                            epsilon: row.get(7)?,
+                            delta: row.get(8)?,
+                            outcome: AuditOutcome::parse(&row.get::<_, String>(9)?),
+                            message: row.get(10)?,
+                        },
+                        row.get::<_, String>(5)?,
+                        row.get::<_, String>(6)?,
+                    ))
+                },
+            )
+            .map_err(|e| e.to_string())?;
+        let mut entries = vec![];
+        for row in rows {
+            let (mut entry, tables, mechanisms) = row.map_err(|e| e.to_string())?;
+            entry.tables = serde_json::from_str(&tables).map_err(|e| e.to_string())?;
+            entry.mechanisms = serde_json::from_str(&mechanisms).map_err(|e| e.to_string())?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// The entries matching `filter` as a JSON array or as CSV with a header line.
+    pub fn export(&self, filter: &AuditFilter, format: ExportFormat) -> Result<String, String> {
+        let entries = self.entries(filter)?;
+        match format {
+            ExportFormat::Json => serde_json::to_string_pretty(&entries).map_err(|e| e.to_string()),
+            ExportFormat::Csv => {
+                let mut lines = vec![CSV_HEADER.to_string()];
+                lines.extend(entries.iter().map(AuditEntry::csv_row));
+                Ok(lines.join("\n") + "\n")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditEntry, AuditFilter, AuditLog, AuditOutcome, ExportFormat};
+
+    fn answered(analyst: &str, query: &str) -> AuditEntry {
+        let mut entry = AuditEntry::new("a", analyst, query);
+        entry.tables = vec!["users".to_string()];
+        entry.mechanisms = vec!["laplace".to_string()];
+        entry.epsilon = 1.0;
+        entry.outcome = AuditOutcome::Answered;
+        entry
+    }
+
+    #[test]
+    fn append_only() {
+        let mut log = AuditLog::in_memory().unwrap();
+        let id = log
+            .record(&answered("alice", "SELECT sum(age) FROM users"))
+            .unwrap();
+        log.record(&AuditEntry::new("a", "bob", "SELECT age FROM users"))
+            .unwrap();
+        assert!(log
+            .connection
+            .execute("DELETE FROM audit_log WHERE id = ?1", [id])
+            .is_err());
+        assert!(log
+            .connection
+            .execute("UPDATE audit_log SET epsilon = 0", [])
+            .is_err());
+
+        let entries = log.entries(&AuditFilter::default()).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(id, entries[0].id);
+        assert_eq!(["users".to_string()].to_vec(), entries[0].tables);
+        let bob = AuditFilter {
+            analyst: Some("bob".to_string()),
+            ..AuditFilter::default()
+        };
+        let entries = log.entries(&bob).unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!(AuditOutcome::Rejected, entries[0].outcome);
+    }
+
+    #[test]
+    fn export() {
+        let mut log = AuditLog::in_memory().unwrap();
+        log.record(&answered("alice", "SELECT sum(age), count(age) FROM users"))
+            .unwrap();
+        let csv = log
+            .export(&AuditFilter::default(), ExportFormat::Csv)
+            .unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[1].contains(",alice,\"SELECT sum(age), count(age) FROM users\",users,"));
+
+        let json = log
+            .export(&AuditFilter::default(), ExportFormat::Json)
+            .unwrap();
+        let entries: Vec<AuditEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(log.entries(&AuditFilter::default()).unwrap(), entries);
+    }
+}
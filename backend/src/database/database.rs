@@ -1,3 +1,4 @@
+use crate::database::identifiers::IdentifierRules;
 use mysql::prelude::Queryable;
 use mysql::{Pool, PooledConn, Row};
 use regex::Regex;
@@ -5,6 +6,7 @@ use rusqlite::types::ValueRef;
 use rusqlite::Connection as SqliteConnection;
 use std::collections::HashMap;
 use std::fmt;
+use std::fs;
 use std::path::Path;
 use std::str;
 
@@ -13,6 +15,12 @@ const URI_PATTERN: &str = r"^mysql:\/\/([^:\/?#]+):([^@\/?#]+)@([^:\/?#]+):(\d+)
 pub struct Database {
     pub flavour: SupportedDatabases,
     pub connection: ConnectionTypes,
+    /// How the backend compares table and column names.
+    pub identifiers: IdentifierRules,
+    /// Identifies the dataset across connections, budgets are kept per fingerprint. It is
+    /// derived from the location of the data and is not stable across copies or aliases,
+    /// see `Ledger`.
+    pub fingerprint: String,
 }
 
 impl fmt::Display for Database {
@@ -21,7 +29,7 @@ impl fmt::Display for Database {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SupportedDatabases {
     MySQL,
     SQLite,
@@ -52,20 +60,29 @@ impl Database {
         let processed_path = path.trim_end();
         if Path::exists(Path::new(processed_path)) {
             let connection: SqliteConnection = SqliteConnection::open(processed_path).unwrap();
+            let location = fs::canonicalize(processed_path).map_err(|e| e.to_string())?;
             return Ok(Database {
                 flavour: SupportedDatabases::SQLite,
                 connection: ConnectionTypes::SQLite(connection),
+                identifiers: IdentifierRules::sqlite(),
+                fingerprint: format!("sqlite:{}", location.display()),
             });
-        } else if Regex::new(URI_PATTERN)
-            .unwrap()
-            .captures(processed_path)
-            .is_some()
-        {
-            let connection_pool: PooledConn =
+        } else if let Some(captures) = Regex::new(URI_PATTERN).unwrap().captures(processed_path) {
+            let mut connection_pool: PooledConn =
                 Pool::new(processed_path).unwrap().get_conn().unwrap();
+            let lower_case_table_names = connection_pool
+                .query_first::<u8, &str>("SELECT @@lower_case_table_names")
+                .map_err(|e| e.to_string())?
+                .unwrap_or_default();
             return Ok(Database {
                 flavour: SupportedDatabases::MySQL,
                 connection: ConnectionTypes::MySQL(connection_pool),
+                identifiers: IdentifierRules::mysql(lower_case_table_names),
+                // The password is left out, changing it does not change the dataset.
+                fingerprint: format!(
+                    "mysql://{}@{}:{}/{}",
+                    &captures[1], &captures[3], &captures[4], &captures[5]
+                ),
             });
         }
         Err(format!("Failed to process database URI: {processed_path} (make sure to add database name in the URI)"))
@@ -158,6 +175,27 @@ impl Database {
         }
         Ok(results)
     }
+    /// Prepares a SQL query without running it, so without reading any data.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - A string slice containing the SQL query to prepare.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query is invalid, e.g. if it references unknown tables or columns.
+    pub fn prepare_query(&mut self, sql: &str) -> Result<(), String> {
+        match &mut self.connection {
+            ConnectionTypes::MySQL(ref mut connector) => {
+                let statement = connector.prep(sql).map_err(|e| e.to_string())?;
+                connector.close(statement).map_err(|e| e.to_string())
+            }
+            ConnectionTypes::SQLite(connector) => connector
+                .prepare(sql)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        }
+    }
     /// Executes a SQL query using the appropriate database connection based on the `Database` instance.
     ///
     /// # Arguments
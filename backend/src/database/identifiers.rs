@@ -0,0 +1,72 @@
+/// How a backend compares identifiers, queries and schema are matched on the normalized form.
+///
+/// Neither backend lets quoting change case sensitivity: a quoted identifier only
+/// allows otherwise reserved words and characters, `"Age"`, `` `Age` `` and `age`
+/// all name the same column. Which quotes delimit an identifier is up to the SQL
+/// dialect the query is parsed with, the parser strips them.
+///
+/// - SQLite compares all identifiers ignoring ASCII case.
+/// - MySQL compares column names and column aliases ignoring case, table names and
+///   table aliases follow `lower_case_table_names`. They are case-sensitive with the
+///   default of `0` on Linux and compared in lowercase otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IdentifierRules {
+    pub case_sensitive_tables: bool,
+}
+
+impl IdentifierRules {
+    pub fn sqlite() -> Self {
+        IdentifierRules {
+            case_sensitive_tables: false,
+        }
+    }
+
+    /// # Parameters
+    /// - `lower_case_table_names`: The value of the server's `lower_case_table_names` variable.
+    pub fn mysql(lower_case_table_names: u8) -> Self {
+        IdentifierRules {
+            case_sensitive_tables: lower_case_table_names == 0,
+        }
+    }
+
+    /// The normalized form of a table name or table alias.
+    pub fn table(&self, name: &str) -> String {
+        if self.case_sensitive_tables {
+            name.to_string()
+        } else {
+            name.to_ascii_lowercase()
+        }
+    }
+
+    /// The normalized form of a column name or column alias.
+    pub fn column(&self, name: &str) -> String {
+        name.to_ascii_lowercase()
+    }
+
+    pub fn same_table(&self, a: &str, b: &str) -> bool {
+        self.table(a) == self.table(b)
+    }
+
+    pub fn same_column(&self, a: &str, b: &str) -> bool {
+        self.column(a) == self.column(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdentifierRules;
+
+    #[test]
+    fn backend_rules() {
+        let sqlite = IdentifierRules::sqlite();
+        assert!(sqlite.same_table("Users", "USERS"));
+        assert!(sqlite.same_column("Age", "age"));
+
+        let mysql = IdentifierRules::mysql(0);
+        assert!(!mysql.same_table("Users", "users"));
+        assert!(mysql.same_column("Age", "AGE"));
+
+        let mysql = IdentifierRules::mysql(1);
+        assert!(mysql.same_table("Users", "users"));
+    }
+}
@@ -0,0 +1,552 @@
+use crate::accountant::Release;
+use crate::database::schema::{ColumnBounds, PrivacyBudget, PrivacyUnit, Table};
+use rusqlite::{params, Connection as SqliteConnection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable overriding where the ledger is stored.
+pub const LEDGER_VARIABLE: &str = "DIFFPRIV_LEDGER";
+
+const LEDGER_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS allowances (
+    fingerprint TEXT NOT NULL,
+    table_name TEXT NOT NULL,
+    epsilon REAL NOT NULL,
+    delta REAL NOT NULL,
+    PRIMARY KEY (fingerprint, table_name)
+);
+CREATE TABLE IF NOT EXISTS analyst_allowances (
+    fingerprint TEXT NOT NULL,
+    analyst TEXT NOT NULL,
+    table_name TEXT NOT NULL,
+    epsilon REAL NOT NULL,
+    delta REAL NOT NULL,
+    PRIMARY KEY (fingerprint, analyst, table_name)
+);
+CREATE TABLE IF NOT EXISTS charges (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    fingerprint TEXT NOT NULL,
+    table_name TEXT NOT NULL,
+    epsilon REAL NOT NULL,
+    delta REAL NOT NULL,
+    -- The zCDP parameter of Gaussian releases, NULL otherwise.
+    rho REAL,
+    analyst TEXT NOT NULL,
+    charged_at INTEGER NOT NULL,
+    -- 'reserved' while the query runs, 'committed' once it produced output.
+    status TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS charges_by_table ON charges (fingerprint, table_name);
+CREATE INDEX IF NOT EXISTS charges_by_analyst ON charges (fingerprint, analyst, table_name);
+CREATE TABLE IF NOT EXISTS column_bounds (
+    fingerprint TEXT NOT NULL,
+    table_name TEXT NOT NULL,
+    column_name TEXT NOT NULL,
+    lower REAL NOT NULL,
+    upper REAL NOT NULL,
+    PRIMARY KEY (fingerprint, table_name, column_name)
+);
+CREATE TABLE IF NOT EXISTS privacy_units (
+    fingerprint TEXT NOT NULL,
+    table_name TEXT NOT NULL,
+    column_name TEXT NOT NULL,
+    max_rows INTEGER NOT NULL,
+    max_groups INTEGER NOT NULL,
+    PRIMARY KEY (fingerprint, table_name)
+);
+";
+
+/// Durable record of the budget every table was given and of every charge against it.
+///
+/// Analysts are given their own allowance per table, every charge is attributed to the
+/// analyst whose query it paid for. The allowance of a table caps what all analysts
+/// spend on it together.
+///
+/// Budgets are kept per dataset fingerprint (see `Database::fingerprint`), every charge is
+/// a release the `Accountant` composes into what a table has spent. Neither restarting
+/// the app nor reconnecting gives spent budget back.
+///
+/// The fingerprint names where the data is, not what it is. A copy of a SQLite file, a
+/// MySQL server reached under another host name or as another user, or a ledger at
+/// another path (see `default_path`, it is per OS user) starts with a fresh budget. The
+/// data owner has to keep analysts from reaching the data any way but the one connection.
+///
+/// A charge is first reserved and committed once the query ran, reservations count as
+/// spent so that a crash in between never gives budget back.
+///
+/// The bounds and privacy units the data owner sets by hand are kept alongside, per
+/// fingerprint, so that reconnecting does not lose them.
+pub struct Ledger {
+    connection: SqliteConnection,
+}
+
+impl Ledger {
+    /// Opens the ledger at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let connection = SqliteConnection::open(path).map_err(|e| e.to_string())?;
+        Ledger::from_connection(connection)
+    }
+
+    /// A ledger that is lost once dropped, for tests.
+    pub fn in_memory() -> Result<Self, String> {
+        let connection = SqliteConnection::open_in_memory().map_err(|e| e.to_string())?;
+        Ledger::from_connection(connection)
+    }
+
+    fn from_connection(connection: SqliteConnection) -> Result<Self, String> {
+        connection
+            .execute_batch(LEDGER_SCHEMA)
+            .map_err(|e| e.to_string())?;
+        Ok(Ledger { connection })
+    }
+
+    /// `DIFFPRIV_LEDGER` if set, otherwise `.diffpriv/ledger.sqlite3` in the home directory.
+    pub fn default_path() -> PathBuf {
+        if let Ok(path) = std::env::var(LEDGER_VARIABLE) {
+            return PathBuf::from(path);
+        }
+        let home = std::env::var("HOME")
+            .or_else(|_| std::env::var("USERPROFILE"))
+            .unwrap_or_else(|_| ".".to_string());
+        Path::new(&home).join(".diffpriv").join("ledger.sqlite3")
+    }
+
+    /// Records the budget the data owner gave `table`.
+    pub fn set_allowance(
+        &mut self,
+        fingerprint: &str,
+        table: &str,
+        budget: &PrivacyBudget,
+    ) -> Result<(), String> {
+        self.connection
+            .execute(
+                "INSERT INTO allowances (fingerprint, table_name, epsilon, delta)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (fingerprint, table_name)
+                DO UPDATE SET epsilon = excluded.epsilon, delta = excluded.delta",
+                params![fingerprint, table, budget.epsilon, budget.delta],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// The budget the data owner gave `table`, if any.
+    pub fn allowance(
+        &self,
+        fingerprint: &str,
+        table: &str,
+    ) -> Result<Option<PrivacyBudget>, String> {
+        self.connection
+            .query_row(
+                "SELECT epsilon, delta FROM allowances WHERE fingerprint = ?1 AND table_name = ?2",
+                params![fingerprint, table],
+                |row| Ok(PrivacyBudget::new(row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    /// Records the budget the data owner gave `analyst` on `table`.
+    pub fn set_analyst_allowance(
+        &mut self,
+        fingerprint: &str,
+        analyst: &str,
+        table: &str,
+        budget: &PrivacyBudget,
+    ) -> Result<(), String> {
+        self.connection
+            .execute(
+                "INSERT INTO analyst_allowances (fingerprint, analyst, table_name, epsilon, delta)
+                VALUES (?1, ?2, ?3, ?4, ?5)
+                ON CONFLICT (fingerprint, analyst, table_name)
+                DO UPDATE SET epsilon = excluded.epsilon, delta = excluded.delta",
+                params![fingerprint, analyst, table, budget.epsilon, budget.delta],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// The budget the data owner gave `analyst` on `table`, if any.
+    pub fn analyst_allowance(
+        &self,
+        fingerprint: &str,
+        analyst: &str,
+        table: &str,
+    ) -> Result<Option<PrivacyBudget>, String> {
+        self.connection
+            .query_row(
+                "SELECT epsilon, delta FROM analyst_allowances
+                WHERE fingerprint = ?1 AND analyst = ?2 AND table_name = ?3",
+                params![fingerprint, analyst, table],
+                |row| Ok(PrivacyBudget::new(row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    /// The analysts given a budget on any table of the dataset, sorted by name.
+    pub fn analysts(&self, fingerprint: &str) -> Result<Vec<String>, String> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT DISTINCT analyst FROM analyst_allowances
+                WHERE fingerprint = ?1 ORDER BY analyst",
+            )
+            .map_err(|e| e.to_string())?;
+        let analysts = statement
+            .query_map(params![fingerprint], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        analysts
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(|e| e.to_string())
+    }
+
+    /// Records the bounds the data owner set on `table`.`column`.
+    pub fn set_bounds(
+        &mut self,
+        fingerprint: &str,
+        table: &str,
+        column: &str,
+        bounds: &ColumnBounds,
+    ) -> Result<(), String> {
+        self.connection
+            .execute(
+                "INSERT INTO column_bounds (fingerprint, table_name, column_name, lower, upper)
+                VALUES (?1, ?2, ?3, ?4, ?5)
+                ON CONFLICT (fingerprint, table_name, column_name)
+                DO UPDATE SET lower = excluded.lower, upper = excluded.upper",
+                params![fingerprint, table, column, bounds.lower, bounds.upper],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// The bounds the data owner set on `table`.`column`, if any.
+    pub fn bounds(
+        &self,
+        fingerprint: &str,
+        table: &str,
+        column: &str,
+    ) -> Result<Option<ColumnBounds>, String> {
+        self.connection
+            .query_row(
+                "SELECT lower, upper FROM column_bounds
+                WHERE fingerprint = ?1 AND table_name = ?2 AND column_name = ?3",
+                params![fingerprint, table, column],
+                |row| {
+                    Ok(ColumnBounds {
+                        lower: row.get(0)?,
+                        upper: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    /// Records the privacy unit the data owner set on `table`, `None` removes it.
+    pub fn set_privacy_unit(
+        &mut self,
+        fingerprint: &str,
+        table: &str,
+        unit: Option<&PrivacyUnit>,
+    ) -> Result<(), String> {
+        let Some(unit) = unit else {
+            self.connection
+                .execute(
+                    "DELETE FROM privacy_units WHERE fingerprint = ?1 AND table_name = ?2",
+                    params![fingerprint, table],
+                )
+                .map_err(|e| e.to_string())?;
+            return Ok(());
+        };
+        self.connection
+            .execute(
+                "INSERT INTO privacy_units
+                (fingerprint, table_name, column_name, max_rows, max_groups)
+                VALUES (?1, ?2, ?3, ?4, ?5)
+                ON CONFLICT (fingerprint, table_name)
+                DO UPDATE SET column_name = excluded.column_name,
+                max_rows = excluded.max_rows, max_groups = excluded.max_groups",
+                params![
+                    fingerprint,
+                    table,
+                    unit.column,
+                    unit.max_rows as i64,
+                    unit.max_groups as i64
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// The privacy unit the data owner set on `table`, if any.
+    pub fn privacy_unit(
+        &self,
+        fingerprint: &str,
+        table: &str,
+    ) -> Result<Option<PrivacyUnit>, String> {
+        self.connection
+            .query_row(
+                "SELECT column_name, max_rows, max_groups FROM privacy_units
+                WHERE fingerprint = ?1 AND table_name = ?2",
+                params![fingerprint, table],
+                |row| {
+                    Ok(PrivacyUnit {
+                        column: row.get(0)?,
+                        max_rows: row.get::<_, i64>(1)? as usize,
+                        max_groups: row.get::<_, i64>(2)? as usize,
+                    })
+                },
+            )
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    /// Sets what was recorded by hand for the dataset on `tables`, freshly read from it.
+    pub fn load_settings(&self, fingerprint: &str, tables: &mut [Table]) -> Result<(), String> {
+        for table in tables.iter_mut() {
+            if let Some(unit) = self.privacy_unit(fingerprint, &table.name)? {
+                table.privacy_unit = Some(unit);
+            }
+            for column in table.columns.iter_mut() {
+                if let Some(bounds) = self.bounds(fingerprint, &table.name, &column.name)? {
+                    column.bounds = Some(bounds);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reserves `release` on `table` for a query of `analyst`.
+    ///
+    /// # Returns
+    /// The id of the reservation, to `commit` or `release` it.
+    pub fn reserve(
+        &mut self,
+        fingerprint: &str,
+        table: &str,
+        analyst: &str,
+        release: &Release,
+    ) -> Result<i64, String> {
+        let charged_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or_default();
+        self.connection
+            .execute(
+                "INSERT INTO charges
+                (fingerprint, table_name, epsilon, delta, rho, analyst, charged_at, status)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 'reserved')",
+                params![
+                    fingerprint,
+                    table,
+                    release.epsilon,
+                    release.delta,
+                    release.rho,
+                    analyst,
+                    charged_at
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Turns a reservation into a charge.
+    pub fn commit(&mut self, reservation: i64) -> Result<(), String> {
+        self.connection
+            .execute(
+                "UPDATE charges SET status = 'committed' WHERE id = ?1",
+                params![reservation],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Gives a reservation back, only for queries that did not read any data.
+    pub fn release(&mut self, reservation: i64) -> Result<(), String> {
+        self.connection
+            .execute(
+                "DELETE FROM charges WHERE id = ?1 AND status = 'reserved'",
+                params![reservation],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Records that `release` was spent on `table` by `analyst`.
+    pub fn charge(
+        &mut self,
+        fingerprint: &str,
+        table: &str,
+        analyst: &str,
+        release: &Release,
+    ) -> Result<(), String> {
+        let reservation = self.reserve(fingerprint, table, analyst, release)?;
+        self.commit(reservation)
+    }
+
+    /// Every release charged or reserved against `table`, oldest first.
+    pub fn releases(&self, fingerprint: &str, table: &str) -> Result<Vec<Release>, String> {
+        self.query_releases(
+            "SELECT epsilon, delta, rho FROM charges
+            WHERE fingerprint = ?1 AND table_name = ?2 ORDER BY id",
+            params![fingerprint, table],
+        )
+    }
+
+    /// Every release charged or reserved against `table` by `analyst`, oldest first.
+    pub fn analyst_releases(
+        &self,
+        fingerprint: &str,
+        analyst: &str,
+        table: &str,
+    ) -> Result<Vec<Release>, String> {
+        self.query_releases(
+            "SELECT epsilon, delta, rho FROM charges
+            WHERE fingerprint = ?1 AND analyst = ?2 AND table_name = ?3 ORDER BY id",
+            params![fingerprint, analyst, table],
+        )
+    }
+
+    fn query_releases(
+        &self,
+        sql: &str,
+        parameters: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<Release>, String> {
+        let mut statement = self.connection.prepare(sql).map_err(|e| e.to_string())?;
+        let releases = statement
+            .query_map(parameters, |row| {
+                Ok(Release {
+                    epsilon: row.get(0)?,
+                    delta: row.get(1)?,
+                    rho: row.get(2)?,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        releases
+            .collect::<Result<Vec<Release>, _>>()
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ledger;
+    use crate::accountant::Release;
+    use crate::database::database::{ConnectionTypes, Database, SupportedDatabases};
+    use crate::database::identifiers::IdentifierRules;
+    use crate::database::schema::{ColumnBounds, PrivacyBudget, PrivacyUnit, Schema};
+    use rusqlite::Connection as SqliteConnection;
+
+    fn pure(epsilon: f64) -> Release {
+        Release::new(PrivacyBudget::new(epsilon, 0.0), None)
+    }
+
+    #[test]
+    fn allowances() {
+        let mut ledger = Ledger::in_memory().unwrap();
+        assert_eq!(None, ledger.allowance("a", "users").unwrap());
+        ledger
+            .set_allowance("a", "users", &PrivacyBudget::new(10.0, 1e-5))
+            .unwrap();
+        ledger
+            .set_allowance("a", "users", &PrivacyBudget::new(5.0, 1e-5))
+            .unwrap();
+        assert_eq!(
+            Some(PrivacyBudget::new(5.0, 1e-5)),
+            ledger.allowance("a", "users").unwrap()
+        );
+        assert_eq!(None, ledger.allowance("b", "users").unwrap());
+
+        ledger
+            .set_analyst_allowance("a", "bob", "users", &PrivacyBudget::new(1.0, 0.0))
+            .unwrap();
+        ledger
+            .set_analyst_allowance("a", "alice", "users", &PrivacyBudget::new(2.0, 0.0))
+            .unwrap();
+        assert_eq!(
+            Some(PrivacyBudget::new(1.0, 0.0)),
+            ledger.analyst_allowance("a", "bob", "users").unwrap()
+        );
+        assert_eq!(
+            None,
+            ledger.analyst_allowance("a", "bob", "orders").unwrap()
+        );
+        assert_eq!(["alice", "bob"].to_vec(), ledger.analysts("a").unwrap());
+        assert!(ledger.analysts("b").unwrap().is_empty());
+    }
+
+    #[test]
+    fn settings() {
+        let connection = SqliteConnection::open_in_memory().unwrap();
+        connection
+            .execute_batch("CREATE TABLE users (age Integer, salary Real);")
+            .unwrap();
+        let mut database = Database {
+            flavour: SupportedDatabases::SQLite,
+            connection: ConnectionTypes::SQLite(connection),
+            identifiers: IdentifierRules::sqlite(),
+            fingerprint: "a".to_string(),
+        };
+        let mut ledger = Ledger::in_memory().unwrap();
+        let bounds = ColumnBounds::new(0.0, 120.0).unwrap();
+        ledger.set_bounds("a", "users", "age", &bounds).unwrap();
+        assert_eq!(Some(bounds), ledger.bounds("a", "users", "age").unwrap());
+        assert_eq!(None, ledger.bounds("b", "users", "age").unwrap());
+        let unit = PrivacyUnit {
+            max_groups: 3,
+            ..PrivacyUnit::new("age")
+        };
+        ledger.set_privacy_unit("a", "users", Some(&unit)).unwrap();
+        ledger.set_privacy_unit("a", "orders", Some(&unit)).unwrap();
+        ledger.set_privacy_unit("a", "orders", None).unwrap();
+        assert_eq!(None, ledger.privacy_unit("a", "orders").unwrap());
+
+        // A schema read again from the database gets back what was set by hand.
+        let mut tables = Schema::from_connection(&mut database);
+        ledger.load_settings("a", &mut tables).unwrap();
+        assert_eq!(Some(bounds), tables[0].columns[0].bounds);
+        assert_eq!(None, tables[0].columns[1].bounds);
+        assert_eq!(Some(unit), tables[0].privacy_unit);
+    }
+
+    #[test]
+    fn reservations() {
+        let mut ledger = Ledger::in_memory().unwrap();
+        let released = ledger.reserve("a", "users", "alice", &pure(1.0)).unwrap();
+        let committed = ledger.reserve("a", "users", "alice", &pure(2.0)).unwrap();
+        assert_eq!(2, ledger.releases("a", "users").unwrap().len());
+
+        ledger.release(released).unwrap();
+        ledger.commit(committed).unwrap();
+        // Committed charges cannot be released.
+        ledger.release(committed).unwrap();
+        assert_eq!([pure(2.0)].to_vec(), ledger.releases("a", "users").unwrap());
+    }
+
+    #[test]
+    fn survives_reopening() {
+        let path =
+            std::env::temp_dir().join(format!("diffpriv-ledger-{}.sqlite3", std::process::id()));
+        let gaussian = Release::new(PrivacyBudget::new(1.0, 1e-6), Some(0.02));
+        {
+            let mut ledger = Ledger::open(&path).unwrap();
+            ledger.charge("a", "users", "alice", &pure(3.0)).unwrap();
+            ledger.charge("a", "users", "bob", &gaussian).unwrap();
+        }
+        let ledger = Ledger::open(&path).unwrap();
+        assert_eq!(
+            [pure(3.0), gaussian].to_vec(),
+            ledger.releases("a", "users").unwrap()
+        );
+        assert_eq!(
+            [gaussian].to_vec(),
+            ledger.analyst_releases("a", "bob", "users").unwrap()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,2 +1,5 @@
+pub mod audit;
 pub mod database;
+pub mod identifiers;
+pub mod ledger;
 pub mod schema;
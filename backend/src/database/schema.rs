@@ -4,7 +4,8 @@ use core::fmt;
 use mysql::prelude::Queryable;
 use mysql::PooledConn;
 use rusqlite::Connection as SqliteConnection;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::ops::Add;
 
 pub struct Schema {
     pub tables: Vec<Table>,
@@ -14,7 +15,108 @@ pub struct Schema {
 pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
-    pub privacy_budget: f64,
+    pub privacy_budget: PrivacyBudget,
+    /// The column identifying whom a row is about, queries over a table with one are
+    /// private for each of them rather than for each row.
+    pub privacy_unit: Option<PrivacyUnit>,
+}
+
+fn one() -> usize {
+    1
+}
+
+/// The column identifying a person and how much of a query a single person may affect.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrivacyUnit {
+    pub column: String,
+    /// How many rows of each group a unit contributes at most.
+    #[serde(default = "one")]
+    pub max_rows: usize,
+    /// How many groups a unit contributes to at most.
+    #[serde(default = "one")]
+    pub max_groups: usize,
+}
+
+impl PrivacyUnit {
+    /// A unit contributing a single row to a single group.
+    pub fn new(column: &str) -> Self {
+        PrivacyUnit {
+            column: column.to_string(),
+            max_rows: 1,
+            max_groups: 1,
+        }
+    }
+
+    /// # Errors
+    /// Returns an error unless a unit may contribute at least one row to one group.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_rows == 0 || self.max_groups == 0 {
+            return Err(format!(
+                "The privacy unit {} must be allowed at least one row and one group",
+                self.column
+            ));
+        }
+        Ok(())
+    }
+
+    /// How many rows of the result a unit can contribute to, in total over all of them.
+    pub fn contributions(&self, grouped: bool) -> usize {
+        if grouped {
+            self.max_rows * self.max_groups
+        } else {
+            self.max_rows
+        }
+    }
+}
+
+/// Rounding errors below this are ignored when checking that a budget covers a cost.
+const BUDGET_TOLERANCE: f64 = 1e-12;
+
+/// An (epsilon, delta) pair, used both for the budget a table has left and
+/// for the cost of a single query.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PrivacyBudget {
+    pub epsilon: f64,
+    pub delta: f64,
+}
+
+impl PrivacyBudget {
+    pub fn new(epsilon: f64, delta: f64) -> Self {
+        PrivacyBudget { epsilon, delta }
+    }
+
+    /// Deducts `cost` from this budget, a budget never goes below zero.
+    pub fn spend(&mut self, cost: &PrivacyBudget) {
+        self.epsilon = (self.epsilon - cost.epsilon).max(0.0);
+        self.delta = (self.delta - cost.delta).max(0.0);
+    }
+
+    /// Whether `cost` can be spent without overdrawing this budget, up to rounding errors.
+    pub fn covers(&self, cost: &PrivacyBudget) -> bool {
+        self.epsilon - cost.epsilon > -BUDGET_TOLERANCE
+            && self.delta - cost.delta > -BUDGET_TOLERANCE
+    }
+
+    /// A budget is exhausted once epsilon is used up or delta was overdrawn.
+    pub fn is_exhausted(&self) -> bool {
+        self.epsilon <= 0.0 || self.delta < 0.0
+    }
+}
+
+impl Add for PrivacyBudget {
+    type Output = PrivacyBudget;
+
+    /// The budget spent by two releases under sequential composition.
+    fn add(self, other: PrivacyBudget) -> PrivacyBudget {
+        PrivacyBudget::new(self.epsilon + other.epsilon, self.delta + other.delta)
+    }
+}
+
+impl fmt::Display for PrivacyBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(epsilon: {}, delta: {})", self.epsilon, self.delta)
+    }
 }
 
 impl fmt::Display for Table {
@@ -23,15 +125,67 @@ impl fmt::Display for Table {
     }
 }
 
+/// The range the data owner declares the values of a column to lie in, values outside of
+/// it are clamped before they reach a mechanism.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ColumnBounds {
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl ColumnBounds {
+    /// # Errors
+    /// Returns an error unless both bounds are finite and `lower < upper`.
+    pub fn new(lower: f64, upper: f64) -> Result<Self, String> {
+        let bounds = ColumnBounds { lower, upper };
+        bounds.validate()?;
+        Ok(bounds)
+    }
+
+    /// # Errors
+    /// Returns an error unless both bounds are finite and `lower < upper`.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.lower.is_finite() && self.upper.is_finite() && self.lower < self.upper) {
+            return Err(format!(
+                "Column bounds must be finite with lower < upper, got [{}, {}]",
+                self.lower, self.upper
+            ));
+        }
+        Ok(())
+    }
+
+    /// `value` moved into the bounds.
+    pub fn clamp(&self, value: f64) -> f64 {
+        value.clamp(self.lower, self.upper)
+    }
+
+    /// The sensitivity of a sum of clamped values, adding or removing a row changes it by
+    /// at most the largest magnitude of the bounds.
+    pub fn sum_sensitivity(&self) -> f64 {
+        self.lower.abs().max(self.upper.abs())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Column {
     pub name: String,
     pub ctype: String,
     pub sensitivity: f64,
+    /// Set by the data owner, required by every aggregate but COUNT. A SUM is clamped into
+    /// them and its sensitivity follows from the bounds instead of `sensitivity`.
+    pub bounds: Option<ColumnBounds>,
     pub usage: Option<String>,
     pub table_name: String, // We need this down the line to make things simple.
 }
 
+impl Column {
+    /// Whether the column holds integers, following SQLite's affinity rule
+    /// (the declared type contains "INT") which also covers MySQL's integer types.
+    pub fn is_integer(&self) -> bool {
+        self.ctype.to_ascii_lowercase().contains("int")
+    }
+}
+
 impl Schema {
     fn generate_mysql_schema(connector: &mut PooledConn) -> Vec<Table> {
         let current_db = connector
@@ -61,6 +215,7 @@ impl Schema {
                         name: column_name,
                         ctype: data_type,
                         sensitivity: 0.0, // To be decided!
+                        bounds: None,
                         usage: None,
                         table_name: name.clone(),
                     },
@@ -69,7 +224,8 @@ impl Schema {
             tables.push(Table {
                 name,
                 columns,
-                privacy_budget: 0.0, // To be decided
+                privacy_budget: PrivacyBudget::default(), // To be decided
+                privacy_unit: None,
             })
         }
         tables
@@ -95,6 +251,7 @@ impl Schema {
                         name: row.get::<_, String>(1).unwrap(),
                         ctype: row.get::<_, String>(2).unwrap(),
                         sensitivity: 0.0,
+                        bounds: None,
                         usage: None,
                         table_name: table_name.clone(),
                     });
@@ -113,7 +270,8 @@ impl Schema {
                         column
                     })
                     .collect::<Vec<Column>>(),
-                privacy_budget: 0.0, // To be decided
+                privacy_budget: PrivacyBudget::default(), // To be decided
+                privacy_unit: None,
             });
         }
         tables
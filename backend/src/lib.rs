@@ -1,3 +1,6 @@
+pub mod accountant;
+pub mod config;
 pub mod database;
+pub mod logging;
 pub mod query;
 pub mod transforms;
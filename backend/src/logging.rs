@@ -0,0 +1,183 @@
+use crate::database::schema::PrivacyBudget;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/*
+Logging never sees the data. Only `Event`s can be logged and none of them carries a value
+read from a table, true or noised: events hold names, counts and privacy parameters that
+the data owner configured or the accountant computed. A new kind of log line needs a new
+variant, which is where a reviewer checks that nothing sensitive slips in.
+
+Owner diagnostics are the exception, they are free-form and may contain true values. They
+are compiled into debug builds only and emitted only once the owner opted in by setting
+`DIFFPRIV_OWNER_DIAGNOSTICS=1`.
+*/
+
+/// Environment variable selecting the log level: `error`, `warn`, `info` or `debug`.
+pub const LOG_LEVEL_VARIABLE: &str = "DIFFPRIV_LOG";
+
+/// Environment variable enabling owner diagnostics in debug builds.
+pub const OWNER_DIAGNOSTICS_VARIABLE: &str = "DIFFPRIV_OWNER_DIAGNOSTICS";
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static OWNER_DIAGNOSTICS: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+impl Level {
+    /// Parses a level name, ignoring case.
+    pub fn parse(level: &str) -> Option<Level> {
+        match level.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Everything that can be logged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A logging option in the environment could not be understood.
+    InvalidSetting { variable: String },
+    /// The schema was (re)loaded from the database.
+    SchemaLoaded { tables: usize },
+    /// The data owner set the sensitivity of a column.
+    SensitivitySet {
+        table: String,
+        column: String,
+        sensitivity: f64,
+    },
+    /// A query of `analyst` was charged, `remaining` is what is left of `table`.
+    BudgetCharged {
+        analyst: String,
+        table: String,
+        remaining: PrivacyBudget,
+    },
+    /// Nothing is left of the budget of `table`.
+    BudgetExhausted { table: String },
+}
+
+impl Event {
+    pub fn level(&self) -> Level {
+        match self {
+            Event::InvalidSetting { .. } => Level::Warn,
+            Event::BudgetExhausted { .. } => Level::Warn,
+            Event::SchemaLoaded { .. } | Event::BudgetCharged { .. } => Level::Info,
+            Event::SensitivitySet { .. } => Level::Debug,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::InvalidSetting { variable } => write!(f, "Ignoring invalid {variable}"),
+            Event::SchemaLoaded { tables } => write!(f, "Loaded the schema of {tables} tables"),
+            Event::SensitivitySet {
+                table,
+                column,
+                sensitivity,
+            } => write!(
+                f,
+                "Set the sensitivity of {table}.{column} to {sensitivity}"
+            ),
+            Event::BudgetCharged {
+                analyst,
+                table,
+                remaining,
+            } => write!(f, "Charged {table} for {analyst}, {remaining} left"),
+            Event::BudgetExhausted { table } => write!(f, "The budget of {table} is exhausted"),
+        }
+    }
+}
+
+/// Reads the log level and the owner diagnostics opt-in from the environment.
+pub fn init_from_env() {
+    if let Ok(level) = std::env::var(LOG_LEVEL_VARIABLE) {
+        match Level::parse(&level) {
+            Some(level) => set_level(level),
+            None => log(Event::InvalidSetting {
+                variable: LOG_LEVEL_VARIABLE.to_string(),
+            }),
+        }
+    }
+    let opted_in = std::env::var(OWNER_DIAGNOSTICS_VARIABLE).is_ok_and(|value| value == "1");
+    OWNER_DIAGNOSTICS.store(cfg!(debug_assertions) && opted_in, Ordering::Relaxed);
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether events at `level` are emitted.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Emits `event` on stderr if its level is enabled.
+pub fn log(event: Event) {
+    if enabled(event.level()) {
+        eprintln!("[{}] {event}", event.level());
+    }
+}
+
+/// Emits the message `message` builds on the owner diagnostics channel.
+///
+/// The channel only exists in debug builds and stays silent unless the owner opted in,
+/// `message` is not even evaluated otherwise.
+#[cfg(debug_assertions)]
+pub fn owner_diagnostic(message: impl FnOnce() -> String) {
+    if OWNER_DIAGNOSTICS.load(Ordering::Relaxed) {
+        eprintln!("[OWNER] {}", message());
+    }
+}
+
+/// Release builds have no owner diagnostics.
+#[cfg(not(debug_assertions))]
+pub fn owner_diagnostic(_message: impl FnOnce() -> String) {}
+
+#[cfg(test)]
+mod tests {
+    use super::{owner_diagnostic, Event, Level};
+    use crate::database::schema::PrivacyBudget;
+
+    #[test]
+    fn levels() {
+        assert_eq!(Some(Level::Warn), Level::parse(" WARN"));
+        assert_eq!(None, Level::parse("verbose"));
+        assert!(Level::Error < Level::Debug);
+        let event = Event::BudgetCharged {
+            analyst: "alice".to_string(),
+            table: "users".to_string(),
+            remaining: PrivacyBudget::new(1.0, 0.0),
+        };
+        assert_eq!(Level::Info, event.level());
+    }
+
+    #[test]
+    fn owner_diagnostics_are_opt_in() {
+        // Not opted in, the message is never built.
+        owner_diagnostic(|| panic!("evaluated without opting in"));
+    }
+}
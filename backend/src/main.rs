@@ -4,113 +4,114 @@ We also don't allow * queries since we need to manage the privacy & sensitivity
 therefore something like: select count(*) from XYX; is treated as an illegal query.
 Note - The password for the database server is generating on the fly.
 */
+use diffpriv::accountant::{Accountant, Composition, Spending};
+use diffpriv::config::{Config, ConfigReport};
+use diffpriv::database::audit::{AuditEntry, AuditFilter, AuditLog, ExportFormat};
 use diffpriv::database::database::Database;
-use diffpriv::database::schema::{Column, Schema, Table};
-use diffpriv::query::analyzer;
-use diffpriv::transforms::laplace_transform;
+use diffpriv::database::ledger::Ledger;
+use diffpriv::database::schema::{ColumnBounds, PrivacyBudget, PrivacyUnit, Schema, Table};
+use diffpriv::logging::{self, Event};
+use diffpriv::query::aggregations::AggregationPolicy;
+use diffpriv::query::allocation::BudgetAllocation;
+use diffpriv::query::pipeline::QueryPipeline;
+use diffpriv::query::results::ResultSet;
+use diffpriv::transforms::mechanisms::MechanismRegistry;
+use diffpriv::transforms::rng::NoiseRng;
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use tauri::State;
 
-static ALLOWED_AGGREGATIONS: [&str; 5] = ["sum(", "avg(", "count(", "min(", "max("];
-
+/// Commands lock the fields they need in the order they are declared, two commands
+/// locking the same fields in a different order could deadlock.
 struct AppState {
-    pub connection: Mutex<Option<Database>>,
     pub schema: Mutex<Option<Vec<Table>>>,
+    pub connection: Mutex<Option<Database>>,
+    pub mechanisms: MechanismRegistry,
+    pub rng: Mutex<NoiseRng>,
+    pub ledger: Mutex<Ledger>,
+    pub accountant: Mutex<Accountant>,
+    pub audit: Mutex<AuditLog>,
+    pub aggregations: Mutex<AggregationPolicy>,
 }
 
-/// Applies differential privacy transformations to the query results.
-///
-/// # Parameters
-/// - `used_columns`: A vector of columns used in the query.
-/// - `query_result`: A vector of hashmaps representing the query results.
-/// - `budget`: The privacy budget for the transformation.
-///
-/// # Returns
-/// A vector of hashmaps with transformed (noised) query results.
-/// _Documentation generated by ChatGPT._
-fn apply_transforms(
-    used_columns: Vec<Column>,
-    query_result: Vec<HashMap<String, String>>,
-    budget: f64,
-) -> Vec<HashMap<String, f64>> {
-    let usage_to_column: HashMap<&String, &Column> = used_columns
-        .iter()
-        .filter_map(|column| column.usage.as_ref().map(|usage| (usage, column)))
-        .collect();
-
-    query_result
-        .iter()
-        .flat_map(|result| {
-            result.iter().filter_map(|(k, v)| {
-                let mut result_map: HashMap<String, f64> = HashMap::new();
-                usage_to_column.get(&k).map(|&column| {
-                    // We need unwrap_or_default to handle Null and we are treating
-                    // nulls as 0 (my decision)
-                    let true_value = v.parse::<f64>().unwrap_or_default();
-                    if budget <= 0.0 {
-                        println!(
-                            "Ran out of budget for {} expect invalid query results!",
-                            &column.table_name
-                        )
-                    }
-                    result_map.insert(
-                        column.usage.as_ref().unwrap().to_owned(),
-                        laplace_transform(true_value, column.sensitivity, budget).to_owned(),
-                    );
-                    result_map
-                })
-            })
-        })
-        .collect()
+/// Loads the schema of `database` with the budgets the accountant says are left and the
+/// settings recorded by hand in the ledger.
+fn load_schema(
+    database: &mut Database,
+    ledger: &Ledger,
+    accountant: &Accountant,
+) -> Result<Vec<Table>, String> {
+    let mut tables = Schema::from_connection(database);
+    ledger.load_settings(&database.fingerprint, &mut tables)?;
+    accountant.load_budgets(ledger, &database.fingerprint, &mut tables)?;
+    logging::log(Event::SchemaLoaded {
+        tables: tables.len(),
+    });
+    Ok(tables)
 }
 
-/// Determines which columns are used in the query.
-///
-/// # Parameters
-/// - `requested`: A vector of requested table names.
-/// - `existing`: A vector of existing tables in the database.
+/// Applies `config` to the tables of `database`, recording the configured budgets in the ledger
+/// and replacing `aggregations` with the configured allow-list.
 ///
 /// # Returns
-/// A vector of tables that are used in the query.
-fn get_used_tables(requested: Vec<String>, existing: &Vec<Table>) -> Vec<Table> {
-    // TODO: Fix the case sensitive issues in this since the table in the requested column
-    // Is always going to be lowercase (that's just how the analyzer works either fix that)
-    existing
-        .iter()
-        .filter(|table| requested.contains(&table.name.to_ascii_lowercase()))
-        .map(|table| table.to_owned())
-        .collect()
+/// What the configuration and the schema disagree on.
+fn apply_config(
+    config: &Config,
+    database: &Database,
+    tables: &mut [Table],
+    ledger: &mut Ledger,
+    accountant: &Accountant,
+    aggregations: &mut AggregationPolicy,
+) -> Result<ConfigReport, String> {
+    let Some(backend) = config.backend(database.flavour) else {
+        return Err(format!(
+            "The configuration has no {:?} section",
+            database.flavour
+        ));
+    };
+    *aggregations = config.aggregation_policy(database.flavour, database.identifiers)?;
+    let report = backend.apply(tables, database.identifiers);
+    for (table, budget) in report.budgets.iter() {
+        ledger.set_allowance(&database.fingerprint, table, budget)?;
+    }
+    accountant.load_budgets(ledger, &database.fingerprint, tables)?;
+    Ok(report)
 }
 
-/// Determines which columns are used in the query.
-///
-/// # Parameters
-/// - `requested`: A vector of requested column names.
-/// - `existing`: A vector of existing columns in the database.
+/// Loads the schema of a newly opened `database` and applies the configuration at
+/// `config_path` to it, if there is one.
 ///
 /// # Returns
-/// A vector of columns that are used in the query.
-/// _Documentation generated by ChatGPT._
-fn get_used_columns(requested: Vec<String>, mut existing: Vec<Column>) -> Vec<Column> {
-    let mut used_columns: Vec<Column> = vec![];
-    let mut index = 0;
-
-    while index < existing.len() {
-        for func in ALLOWED_AGGREGATIONS.iter() {
-            if requested.contains(&format!("{func}{})", existing[index].name)) {
-                existing[index].usage = Some(format!("{func}{})", existing[index].name));
-                used_columns.push(existing[index].to_owned());
-            }
-        }
-
-        if requested.contains(&existing[index].name) {
-            used_columns.push(existing[index].to_owned());
+/// The tables and the message `connect` answers with.
+fn open_schema(
+    database: &mut Database,
+    config_path: &Path,
+    ledger: &mut Ledger,
+    accountant: &Accountant,
+    aggregations: &mut AggregationPolicy,
+) -> Result<(Vec<Table>, String), String> {
+    let mut tables = load_schema(database, ledger, accountant)?;
+    *aggregations = AggregationPolicy::default();
+    // The configuration is optional, the sensitivities can be set by hand instead.
+    let message = if config_path.exists() {
+        match Config::from_file(config_path).and_then(|config| {
+            apply_config(
+                &config,
+                database,
+                &mut tables,
+                ledger,
+                accountant,
+                aggregations,
+            )
+        }) {
+            Ok(report) => format!("Connected, {report}"),
+            Err(error) => format!("Connected without the configuration: {error}"),
         }
-
-        index += 1;
-    }
-    used_columns
+    } else {
+        "Connected".to_string()
+    };
+    Ok((tables, message))
 }
 
 /// Resets the sensitivities of all columns in the database schema.
@@ -119,13 +120,15 @@ fn get_used_columns(requested: Vec<String>, mut existing: Vec<Column>) -> Vec<Co
 /// - `app_state`: The shared application state containing the database connection and schema.
 /// _Documentation generated by ChatGPT._
 #[tauri::command]
-fn reset_sensitivities(app_state: State<'_, Arc<AppState>>) {
+fn reset_sensitivities(app_state: State<'_, Arc<AppState>>) -> Result<(), String> {
     let mut schema = app_state.schema.lock().unwrap();
     let mut database = app_state.connection.lock().unwrap();
+    let ledger = app_state.ledger.lock().unwrap();
+    let accountant = app_state.accountant.lock().unwrap();
     if let Some(database) = database.as_mut() {
-        *schema = Some(Schema::from_connection(database))
+        *schema = Some(load_schema(database, &ledger, &accountant)?)
     }
-    println!("Reset sensitivities!");
+    Ok(())
 }
 
 /// Resets the database connection.
@@ -141,91 +144,290 @@ fn reset_connection(app_state: State<'_, Arc<AppState>>) {
     *database = None;
 }
 
-fn sanitize_input(input: &str) -> String {
-    input.replace("“", "\"").replace("”", "\"")
-}
-
 /// Executes an SQL query with differential privacy applied.
 ///
 /// # Parameters
 /// - `app_state`: The shared application state containing the database connection and schema.
+/// - `analyst`: The analyst running the query, it is charged to their budget, an analyst
+///   who was given none cannot query. The name is a label the client picks, not
+///   authentication, anyone can query under any name.
 /// - `query`: The SQL query to be executed.
-/// - `budget`: The privacy budget for the query.
+/// - `budget`: The epsilon spent on the query.
+/// - `mechanism`: The name of a registered noise mechanism, defaults to Laplace.
+/// - `delta`: The delta spent on the query, required by (epsilon, delta) mechanisms.
+/// - `allocation`: How `budget` is spread over the aggregates, split evenly by default.
 ///
 /// # Returns
 /// A result containing either the transformed query results or an error message.
 /// _Documentation generated by ChatGPT._
+///
+/// # Errors
+/// Returns an error if no database is connected or the query cannot be answered.
 #[tauri::command]
 fn execute_sql(
     app_state: State<'_, Arc<AppState>>,
+    analyst: String,
     query: String,
     budget: f64,
-) -> Result<Vec<HashMap<String, f64>>, String> {
-    let sanitized_query = sanitize_input(query.as_str());
-    let mut has_budget = true;
+    mechanism: Option<String>,
+    delta: Option<f64>,
+    allocation: Option<BudgetAllocation>,
+) -> Result<ResultSet, String> {
+    if analyst.trim().is_empty() {
+        return Err("Queries have to be attributed to an analyst".to_string());
+    }
+    let mut schema = app_state.schema.lock().unwrap();
     let mut database = app_state.connection.lock().unwrap();
-    let connection = database.as_mut().unwrap();
+    let mut rng = app_state.rng.lock().unwrap();
+    let mut ledger = app_state.ledger.lock().unwrap();
+    let accountant = app_state.accountant.lock().unwrap();
+    let mut audit = app_state.audit.lock().unwrap();
+    let aggregations = app_state.aggregations.lock().unwrap();
+    // Panicking here would poison the locks held above and fail every later command.
+    let (Some(tables), Some(database)) = (schema.as_mut(), database.as_mut()) else {
+        return Err("Not connected".to_string());
+    };
+    let mut pipeline = QueryPipeline {
+        database,
+        tables,
+        mechanisms: &app_state.mechanisms,
+        aggregations: &aggregations,
+        ledger: &mut ledger,
+        accountant: &accountant,
+        analyst: analyst.trim(),
+        audit: &mut audit,
+        rng: &mut *rng,
+    };
+    pipeline.execute(
+        &query,
+        budget,
+        mechanism.as_deref(),
+        delta,
+        &allocation.unwrap_or_default(),
+    )
+}
+
+/// Sets the allowed privacy budget for each column after which no more queries are processed for that column
+///
+/// The budgets are recorded in the ledger, what was already spent on a table stays spent.
+/// Tables left out of `budgets` keep the budget they have, e.g. from `conf.json`.
+///
+/// # Parameters
+/// - `app_state`: The shared application state containing the database connection and schema.
+/// - `budgets`: A hashmap of table names to table (epsilon, delta) budgets.
+#[tauri::command]
+fn set_budgets(
+    app_state: State<'_, Arc<AppState>>,
+    budgets: HashMap<String, PrivacyBudget>,
+) -> Result<String, String> {
     let mut schema = app_state.schema.lock().unwrap();
-    let database_tables = schema.as_mut().unwrap();
-    let analyzer = analyzer::SqlAnalyzer::new(&query);
-    let requested_columns = analyzer.columns_from_sql();
-    let requested_tables = analyzer.tables_from_sql();
-    let existing_columns: Vec<Column> = database_tables
-        .iter()
-        .flat_map(|table| table.columns.clone())
-        .collect();
-    let used_columns = get_used_columns(requested_columns, existing_columns);
-    let used_tables = get_used_tables(requested_tables, database_tables);
+    let database = app_state.connection.lock().unwrap();
+    let mut ledger = app_state.ledger.lock().unwrap();
+    let accountant = app_state.accountant.lock().unwrap();
+    if let (Some(database_tables), Some(database)) = (schema.as_mut(), database.as_ref()) {
+        for table in database_tables.iter_mut() {
+            let Some(budget) = budgets.get(&table.name) else {
+                continue;
+            };
+            ledger.set_allowance(&database.fingerprint, &table.name, budget)?;
+            table.privacy_budget =
+                accountant.remaining(&ledger, &database.fingerprint, &table.name)?;
+        }
+        return Ok("Set table budget!".to_string());
+    }
+    Err("Unable to establish connection with the database!".to_string())
+}
 
-    // Deduct budget from this table
-    for table in database_tables.iter_mut() {
-        used_tables.iter().for_each(|used_table| {
-            if table.name == used_table.name {
-                let message = format!(
-                    "Reducing {} budget from {} to {}",
-                    table.name,
-                    table.privacy_budget,
-                    table.privacy_budget - budget,
-                );
-                println!("{message}");
-                table.privacy_budget -= budget;
+/// Sets the budget of an analyst on each table, what they spent on a table stays spent.
+/// Analysts without a budget of their own on a table cannot query it.
+///
+/// # Parameters
+/// - `app_state`: The shared application state containing the database connection and schema.
+/// - `analyst`: The name identifying the analyst, a label and not authentication: it only
+///   splits the table budgets between the names queries are run under.
+/// - `budgets`: A hashmap of table names to the (epsilon, delta) the analyst may spend.
+///
+/// # Errors
+/// Returns an error if a budget exceeds the budget of its table, all analysts together
+/// are capped by the table budget in any case.
+#[tauri::command]
+fn set_analyst_budgets(
+    app_state: State<'_, Arc<AppState>>,
+    analyst: String,
+    budgets: HashMap<String, PrivacyBudget>,
+) -> Result<String, String> {
+    let analyst = analyst.trim();
+    if analyst.is_empty() {
+        return Err("Analysts need a name".to_string());
+    }
+    let schema = app_state.schema.lock().unwrap();
+    let database = app_state.connection.lock().unwrap();
+    let mut ledger = app_state.ledger.lock().unwrap();
+    if let (Some(tables), Some(database)) = (schema.as_ref(), database.as_ref()) {
+        for table in tables.iter() {
+            let budget = budgets.get(&table.name).copied().unwrap_or_default();
+            let allowance = ledger
+                .allowance(&database.fingerprint, &table.name)?
+                .unwrap_or_default();
+            if budget.epsilon > allowance.epsilon || budget.delta > allowance.delta {
+                return Err(format!(
+                    "The budget of {analyst} on {} exceeds the budget of the table, {allowance}",
+                    table.name
+                ));
             }
-        });
+        }
+        for table in tables.iter() {
+            let budget = budgets.get(&table.name).copied().unwrap_or_default();
+            ledger.set_analyst_allowance(&database.fingerprint, analyst, &table.name, &budget)?;
+        }
+        return Ok(format!("Set the budget of {analyst}!"));
     }
+    Err("Unable to establish connection with the database!".to_string())
+}
 
-    // Check here if the tables that are being used have enough budget to execute this query
-    used_tables.iter().for_each(|table| {
-        if table.privacy_budget <= 0.0 {
-            has_budget = false;
+/// Reports what every analyst was given and spent on every table.
+///
+/// # Parameters
+/// - `app_state`: The shared application state containing the ledger and the accountant.
+#[tauri::command]
+fn get_analyst_spending(app_state: State<'_, Arc<AppState>>) -> Result<Vec<Spending>, String> {
+    let schema = app_state.schema.lock().unwrap();
+    let database = app_state.connection.lock().unwrap();
+    let ledger = app_state.ledger.lock().unwrap();
+    let accountant = app_state.accountant.lock().unwrap();
+    match (schema.as_ref(), database.as_ref()) {
+        (Some(tables), Some(database)) => {
+            accountant.spending(&ledger, &database.fingerprint, tables)
         }
-    });
-    if has_budget {
-        let query_result = connection.execute_query(&sanitized_query)?;
-        let transformed_query_results = apply_transforms(used_columns, query_result, budget);
-        return Ok(transformed_query_results);
+        _ => Err("No tables loaded yet!".to_string()),
     }
-    Err("Insufficient budget!".to_string())
 }
 
-/// Sets the allowed privacy budget for each column after which no more queries are processed for that column
+/// Applies the privacy configuration to the connected database.
 ///
 /// # Parameters
 /// - `app_state`: The shared application state containing the database connection and schema.
-/// - `budgets`: A hashmap of table names to table budgets.
+/// - `path`: The configuration file, `conf.json` (or `DIFFPRIV_CONFIG`) by default.
+///
+/// # Returns
+/// The tables and columns the configuration and the schema disagree on.
 #[tauri::command]
-fn set_budgets(
+fn load_config(
+    app_state: State<'_, Arc<AppState>>,
+    path: Option<String>,
+) -> Result<ConfigReport, String> {
+    let config = match path {
+        Some(path) => Config::from_file(Path::new(&path))?,
+        None => Config::from_file(&Config::default_path())?,
+    };
+    let mut schema = app_state.schema.lock().unwrap();
+    let database = app_state.connection.lock().unwrap();
+    let mut ledger = app_state.ledger.lock().unwrap();
+    let accountant = app_state.accountant.lock().unwrap();
+    let mut aggregations = app_state.aggregations.lock().unwrap();
+    match (schema.as_mut(), database.as_ref()) {
+        (Some(tables), Some(database)) => apply_config(
+            &config,
+            database,
+            tables,
+            &mut ledger,
+            &accountant,
+            &mut aggregations,
+        ),
+        _ => Err("Unable to establish connection with the database!".to_string()),
+    }
+}
+
+/// Reads the audit log.
+///
+/// # Parameters
+/// - `app_state`: The shared application state containing the audit log.
+/// - `filter`: Restricts the entries by dataset, analyst or time, all entries by default.
+///
+/// # Returns
+/// The matching entries, oldest first.
+#[tauri::command]
+fn get_audit_log(
+    app_state: State<'_, Arc<AppState>>,
+    filter: Option<AuditFilter>,
+) -> Result<Vec<AuditEntry>, String> {
+    let audit = app_state.audit.lock().unwrap();
+    audit.entries(&filter.unwrap_or_default())
+}
+
+/// Exports the audit log.
+///
+/// # Parameters
+/// - `app_state`: The shared application state containing the audit log.
+/// - `format`: `json` or `csv`.
+/// - `filter`: Restricts the entries by dataset, analyst or time, all entries by default.
+///
+/// # Returns
+/// The matching entries in `format`.
+#[tauri::command]
+fn export_audit_log(
     app_state: State<'_, Arc<AppState>>,
-    budgets: HashMap<String, f64>,
+    format: ExportFormat,
+    filter: Option<AuditFilter>,
 ) -> Result<String, String> {
+    let audit = app_state.audit.lock().unwrap();
+    audit.export(&filter.unwrap_or_default(), format)
+}
+
+/// Selects how the accountant composes the queries run against a table.
+///
+/// Every composition bounds the whole history of a table, switching re-evaluates what
+/// was already spent and never gives budget back that basic composition would not.
+///
+/// # Parameters
+/// - `app_state`: The shared application state containing the accountant.
+/// - `composition`: Basic, advanced, zCDP or Renyi DP composition. Advanced composition
+///   only applies to pure releases of at most the epsilon it is given.
+///
+/// # Errors
+/// Returns an error if the parameters of the composition are out of range.
+#[tauri::command]
+fn set_composition(
+    app_state: State<'_, Arc<AppState>>,
+    composition: Composition,
+) -> Result<(), String> {
+    composition.validate()?;
     let mut schema = app_state.schema.lock().unwrap();
-    if let Some(database_tables) = schema.as_mut() {
-        database_tables.iter_mut().for_each(|table| {
-            let budget = budgets.get(&table.name).unwrap_or(&0.0).to_owned();
-            table.privacy_budget = budget;
-        });
-        return Ok("Set table budget!".to_string());
+    let database = app_state.connection.lock().unwrap();
+    let ledger = app_state.ledger.lock().unwrap();
+    let mut accountant = app_state.accountant.lock().unwrap();
+    accountant.composition = composition;
+    if let (Some(tables), Some(database)) = (schema.as_mut(), database.as_ref()) {
+        accountant.load_budgets(&ledger, &database.fingerprint, tables)?;
+    }
+    Ok(())
+}
+
+/// Reports the (epsilon, delta) spent on every table under the selected composition.
+///
+/// # Parameters
+/// - `app_state`: The shared application state containing the ledger and the accountant.
+///
+/// # Returns
+/// A hashmap of table names to what was spent on them.
+#[tauri::command]
+fn get_privacy_spent(
+    app_state: State<'_, Arc<AppState>>,
+) -> Result<HashMap<String, PrivacyBudget>, String> {
+    let schema = app_state.schema.lock().unwrap();
+    let database = app_state.connection.lock().unwrap();
+    let ledger = app_state.ledger.lock().unwrap();
+    let accountant = app_state.accountant.lock().unwrap();
+    match (schema.as_ref(), database.as_ref()) {
+        (Some(tables), Some(database)) => tables
+            .iter()
+            .map(|table| {
+                let spent = accountant.spent(&ledger, &database.fingerprint, &table.name)?;
+                Ok((table.name.to_owned(), spent))
+            })
+            .collect(),
+        _ => Err("No tables loaded yet!".to_string()),
     }
-    Err("Unable to establish connection with the database!".to_string())
 }
 
 /// Sets the sensitivities for columns in the database schema.
@@ -248,10 +450,11 @@ fn set_sensitivities(
             let table_sensitivity = sensitivities.get(&table.name).unwrap(); // We will for sure have this in the sensitivities
             table.columns.iter_mut().for_each(|column| {
                 let field_sensitivity = table_sensitivity.get(&column.name).unwrap();
-                println!(
-                    "Setting sensitivity for {} to {}",
-                    &column.name, field_sensitivity
-                );
+                logging::log(Event::SensitivitySet {
+                    table: table.name.to_owned(),
+                    column: column.name.to_owned(),
+                    sensitivity: *field_sensitivity,
+                });
                 column.sensitivity = field_sensitivity.to_owned();
             })
         });
@@ -261,6 +464,108 @@ fn set_sensitivities(
     }
 }
 
+/// Sets the bounds of columns, aggregates other than COUNT are only answered over columns
+/// with bounds and SUM is clamped into them. The bounds are recorded in the ledger and
+/// survive reconnecting.
+///
+/// # Parameters
+/// - `app_state`: The shared application state containing the schema and the ledger.
+/// - `bounds`: The bounds of columns by table, columns left out keep theirs.
+///
+/// # Errors
+/// Returns an error if no database is connected or a bound is invalid, nothing is set then.
+#[tauri::command]
+fn set_bounds(
+    app_state: State<'_, Arc<AppState>>,
+    bounds: HashMap<String, HashMap<String, ColumnBounds>>,
+) -> Result<(), String> {
+    for (table, columns) in bounds.iter() {
+        for (column, column_bounds) in columns.iter() {
+            column_bounds
+                .validate()
+                .map_err(|error| format!("{error} on {table}.{column}"))?;
+        }
+    }
+    let mut schema = app_state.schema.lock().unwrap();
+    let database = app_state.connection.lock().unwrap();
+    let mut ledger = app_state.ledger.lock().unwrap();
+    let (Some(tables), Some(database)) = (schema.as_mut(), database.as_ref()) else {
+        return Err("Not connected to a database".to_string());
+    };
+    for table in tables.iter_mut() {
+        let Some(columns) = bounds.get(&table.name) else {
+            continue;
+        };
+        for column in table.columns.iter_mut() {
+            if let Some(column_bounds) = columns.get(&column.name) {
+                ledger.set_bounds(
+                    &database.fingerprint,
+                    &table.name,
+                    &column.name,
+                    column_bounds,
+                )?;
+                column.bounds = Some(*column_bounds);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sets the privacy units of tables, queries over a table with one are private for each
+/// unit rather than for each row. The units are recorded in the ledger and survive
+/// reconnecting.
+///
+/// # Parameters
+/// - `app_state`: The shared application state containing the schema and the ledger.
+/// - `units`: The privacy unit of tables, `None` removes it, tables left out keep theirs.
+///
+/// # Errors
+/// Returns an error if no database is connected or a unit is not a column of its table,
+/// nothing is set then.
+#[tauri::command]
+fn set_privacy_units(
+    app_state: State<'_, Arc<AppState>>,
+    units: HashMap<String, Option<PrivacyUnit>>,
+) -> Result<(), String> {
+    let mut schema = app_state.schema.lock().unwrap();
+    let database = app_state.connection.lock().unwrap();
+    let mut ledger = app_state.ledger.lock().unwrap();
+    let (Some(tables), Some(database)) = (schema.as_mut(), database.as_ref()) else {
+        return Err("Not connected to a database".to_string());
+    };
+    for table in tables.iter() {
+        if let Some(Some(unit)) = units.get(&table.name) {
+            unit.validate()?;
+            if !table
+                .columns
+                .iter()
+                .any(|column| column.name == unit.column)
+            {
+                return Err(format!("{} has no column {}", table.name, unit.column));
+            }
+        }
+    }
+    for table in tables.iter_mut() {
+        if let Some(unit) = units.get(&table.name) {
+            ledger.set_privacy_unit(&database.fingerprint, &table.name, unit.as_ref())?;
+            table.privacy_unit = unit.clone();
+        }
+    }
+    Ok(())
+}
+
+/// Lists the noise mechanisms queries can select.
+///
+/// # Parameters
+/// - `app_state`: The shared application state containing the mechanism registry.
+///
+/// # Returns
+/// The names of all registered mechanisms.
+#[tauri::command]
+fn get_mechanisms(app_state: State<'_, Arc<AppState>>) -> Vec<String> {
+    app_state.mechanisms.names()
+}
+
 /// Retrieves the tables from the database schema.
 ///
 /// # Parameters
@@ -293,14 +598,24 @@ fn connect(
     app_state: State<'_, Arc<AppState>>, // Arc since we share between multiple threads (Safely).
     database_path: String,
 ) -> Result<String, String> {
-    let mut connection_gaurd = app_state.connection.lock().unwrap();
     let mut schema_gaurd = app_state.schema.lock().unwrap();
+    let mut connection_gaurd = app_state.connection.lock().unwrap();
+    let mut ledger = app_state.ledger.lock().unwrap();
+    let accountant = app_state.accountant.lock().unwrap();
+    let mut aggregations = app_state.aggregations.lock().unwrap();
     if connection_gaurd.is_none() {
         match Database::new(&database_path) {
             Ok(mut connection) => {
-                *schema_gaurd = Some(Schema::from_connection(&mut connection));
+                let (tables, message) = open_schema(
+                    &mut connection,
+                    &Config::default_path(),
+                    &mut ledger,
+                    &accountant,
+                    &mut aggregations,
+                )?;
+                *schema_gaurd = Some(tables);
                 *connection_gaurd = Some(connection);
-                Ok("Connected".to_string())
+                Ok(message)
             }
             Err(msg) => Err(msg),
         }
@@ -310,20 +625,104 @@ fn connect(
 }
 
 fn main() {
+    logging::init_from_env();
     tauri::Builder::default()
         .manage(Arc::new(AppState {
-            connection: Mutex::new(None),
             schema: Mutex::new(None),
+            connection: Mutex::new(None),
+            mechanisms: MechanismRegistry::default(),
+            rng: Mutex::new(NoiseRng::from_env().expect("Unable to set up the noise RNG")),
+            ledger: Mutex::new(
+                Ledger::open(&Ledger::default_path()).expect("Unable to open the budget ledger"),
+            ),
+            accountant: Mutex::new(Accountant::default()),
+            audit: Mutex::new(
+                AuditLog::open(&AuditLog::default_path()).expect("Unable to open the audit log"),
+            ),
+            aggregations: Mutex::new(AggregationPolicy::default()),
         }))
         .invoke_handler(tauri::generate_handler![
             connect,
             get_tables,
             set_sensitivities,
+            set_bounds,
+            set_privacy_units,
             execute_sql,
             reset_sensitivities,
             reset_connection,
             set_budgets,
+            get_mechanisms,
+            set_composition,
+            get_privacy_spent,
+            set_analyst_budgets,
+            get_analyst_spending,
+            load_config,
+            get_audit_log,
+            export_audit_log,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::open_schema;
+    use diffpriv::accountant::Accountant;
+    use diffpriv::database::database::Database;
+    use diffpriv::database::ledger::Ledger;
+    use diffpriv::database::schema::{ColumnBounds, PrivacyUnit};
+    use diffpriv::query::aggregations::AggregationPolicy;
+    use std::fs;
+
+    #[test]
+    fn connect_keeps_saved_settings() {
+        let directory =
+            std::env::temp_dir().join(format!("diffpriv-connect-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let database_path = directory.join("users.sqlite3");
+        let _ = fs::remove_file(&database_path);
+        rusqlite::Connection::open(&database_path)
+            .unwrap()
+            .execute_batch("CREATE TABLE users (id INTEGER, age INTEGER, salary REAL);")
+            .unwrap();
+        // Only a sensitivity for age and no privacy unit.
+        let config_path = directory.join("conf.json");
+        fs::write(
+            &config_path,
+            r#"{"sqlite": {"tables": {"users": {"age": 1.0, "salary": 2.0}}}}"#,
+        )
+        .unwrap();
+
+        let mut database = Database::new(database_path.to_str().unwrap()).unwrap();
+        let mut ledger = Ledger::in_memory().unwrap();
+        let bounds = ColumnBounds::new(0.0, 120.0).unwrap();
+        let unit = PrivacyUnit::new("id");
+        ledger
+            .set_bounds(&database.fingerprint, "users", "age", &bounds)
+            .unwrap();
+        ledger
+            .set_privacy_unit(&database.fingerprint, "users", Some(&unit))
+            .unwrap();
+
+        let (tables, message) = open_schema(
+            &mut database,
+            &config_path,
+            &mut ledger,
+            &Accountant::default(),
+            &mut AggregationPolicy::default(),
+        )
+        .unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(message.starts_with("Connected, "), "{message}");
+        let users = tables.iter().find(|table| table.name == "users").unwrap();
+        assert_eq!(Some(unit), users.privacy_unit);
+        let age = users
+            .columns
+            .iter()
+            .find(|column| column.name == "age")
+            .unwrap();
+        assert_eq!(1.0, age.sensitivity);
+        assert_eq!(Some(bounds), age.bounds);
+    }
+}
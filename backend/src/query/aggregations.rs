@@ -0,0 +1,199 @@
+use crate::database::identifiers::IdentifierRules;
+use crate::database::schema::Column;
+use std::collections::HashMap;
+
+/// Aggregates the pipeline knows how to answer with differential privacy, those allowed
+/// by default first.
+pub static SUPPORTED_AGGREGATIONS: [&str; 9] = [
+    "count",
+    "sum",
+    "avg",
+    "min",
+    "max",
+    "median",
+    "percentile",
+    "variance",
+    "stddev",
+];
+
+/// Aggregates allowed on columns the data owner did not restrict, the same as in the
+/// shipped `conf.json`. All but COUNT are only answered on columns with bounds.
+pub static DEFAULT_AGGREGATIONS: &[&str] = SUPPORTED_AGGREGATIONS.split_at(3).0;
+
+/// Which aggregates may be computed over which columns.
+///
+/// The most specific rule wins: a column's own list, then its table's list, then the
+/// default list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregationPolicy {
+    default: Vec<String>,
+    tables: HashMap<String, Vec<String>>,
+    columns: HashMap<(String, String), Vec<String>>,
+    identifiers: IdentifierRules,
+}
+
+impl Default for AggregationPolicy {
+    fn default() -> Self {
+        AggregationPolicy {
+            default: DEFAULT_AGGREGATIONS.iter().map(|f| f.to_string()).collect(),
+            tables: HashMap::new(),
+            columns: HashMap::new(),
+            identifiers: IdentifierRules::default(),
+        }
+    }
+}
+
+/// The name of the aggregate `function` refers to, e.g. `sum` for `SUM(` as written in
+/// `conf.json`.
+///
+/// # Errors
+/// Returns an error if the pipeline cannot answer the aggregate.
+pub fn parse_function(function: &str) -> Result<String, String> {
+    let name = function.trim().trim_end_matches('(').trim().to_lowercase();
+    if SUPPORTED_AGGREGATIONS.contains(&name.as_str()) {
+        Ok(name)
+    } else {
+        Err(format!(
+            "Unsupported aggregation {function}, supported are: {}",
+            SUPPORTED_AGGREGATIONS.join(", ")
+        ))
+    }
+}
+
+fn parse_functions(functions: &[String]) -> Result<Vec<String>, String> {
+    functions.iter().map(|f| parse_function(f)).collect()
+}
+
+impl AggregationPolicy {
+    /// A policy allowing `default` on every column, tables and columns are matched
+    /// following `identifiers`.
+    pub fn new(default: &[String], identifiers: IdentifierRules) -> Result<Self, String> {
+        Ok(AggregationPolicy {
+            default: parse_functions(default)?,
+            tables: HashMap::new(),
+            columns: HashMap::new(),
+            identifiers,
+        })
+    }
+
+    /// Allows only `functions` on the columns of `table` without a list of their own.
+    pub fn restrict_table(&mut self, table: &str, functions: &[String]) -> Result<(), String> {
+        self.tables
+            .insert(self.identifiers.table(table), parse_functions(functions)?);
+        Ok(())
+    }
+
+    /// Allows only `functions` on `column` of `table`.
+    pub fn restrict_column(
+        &mut self,
+        table: &str,
+        column: &str,
+        functions: &[String],
+    ) -> Result<(), String> {
+        self.columns.insert(
+            (
+                self.identifiers.table(table),
+                self.identifiers.column(column),
+            ),
+            parse_functions(functions)?,
+        );
+        Ok(())
+    }
+
+    /// The aggregates allowed on `column` of `table`.
+    pub fn allowed(&self, table: &str, column: &str) -> &[String] {
+        let table = self.identifiers.table(table);
+        self.columns
+            .get(&(table.to_owned(), self.identifiers.column(column)))
+            .or_else(|| self.tables.get(&table))
+            .unwrap_or(&self.default)
+    }
+
+    /// Checks that `function` may be computed over `column`.
+    ///
+    /// # Errors
+    /// Returns an error naming the allowed aggregates if it may not.
+    pub fn check(&self, function: &str, column: &Column) -> Result<(), String> {
+        let function = function.to_lowercase();
+        if !SUPPORTED_AGGREGATIONS.contains(&function.as_str()) {
+            return Err(format!(
+                "{} is not supported, supported aggregates are: {}",
+                function.to_uppercase(),
+                SUPPORTED_AGGREGATIONS.join(", ")
+            ));
+        }
+        let allowed = self.allowed(&column.table_name, &column.name);
+        if allowed.contains(&function) {
+            return Ok(());
+        }
+        Err(format!(
+            "{} is not allowed on {}.{}, allowed aggregates are: {}",
+            function.to_uppercase(),
+            column.table_name,
+            column.name,
+            if allowed.is_empty() {
+                "none".to_string()
+            } else {
+                allowed.join(", ")
+            }
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_function, AggregationPolicy};
+    use crate::database::identifiers::IdentifierRules;
+    use crate::database::schema::Column;
+
+    fn column(table: &str, name: &str) -> Column {
+        Column {
+            name: name.to_string(),
+            ctype: "INTEGER".to_string(),
+            sensitivity: 1.0,
+            bounds: None,
+            usage: None,
+            table_name: table.to_string(),
+        }
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(Ok("avg".to_string()), parse_function("AVG("));
+        assert_eq!(Ok("sum".to_string()), parse_function("sum"));
+        assert_eq!(Ok("percentile".to_string()), parse_function("PERCENTILE("));
+        assert!(parse_function("mode(").is_err());
+    }
+
+    #[test]
+    fn most_specific_rule_wins() {
+        let mut policy = AggregationPolicy::new(
+            &["count(".to_string(), "sum(".to_string()],
+            IdentifierRules::sqlite(),
+        )
+        .unwrap();
+        policy
+            .restrict_table("Users", &["count(".to_string()])
+            .unwrap();
+        policy
+            .restrict_column("users", "Age", &["avg(".to_string()])
+            .unwrap();
+        assert!(policy.check("sum", &column("orders", "total")).is_ok());
+        assert!(policy.check("avg", &column("orders", "total")).is_err());
+        assert!(policy.check("sum", &column("users", "salary")).is_err());
+        assert!(policy.check("avg", &column("users", "age")).is_ok());
+        assert!(policy.check("count", &column("users", "age")).is_err());
+        assert!(policy.check("MODE", &column("orders", "total")).is_err());
+    }
+
+    #[test]
+    fn default_policy() {
+        let policy = AggregationPolicy::default();
+        for function in ["count", "sum", "avg"] {
+            assert!(policy.check(function, &column("users", "age")).is_ok());
+        }
+        for function in ["min", "max", "median", "percentile", "variance", "stddev"] {
+            assert!(policy.check(function, &column("users", "age")).is_err());
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::database::schema::PrivacyBudget;
+use serde::{Deserialize, Serialize};
+
+/// How the epsilon and delta requested for a query are spread over its aggregates.
+///
+/// Every aggregate is a separate release, under sequential composition the query costs
+/// the sum of what its aggregates are noised with. Partition selection of a grouped query
+/// is a release as well, see `set_aside_selection`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BudgetAllocation {
+    /// The request is split evenly, the query costs what was requested.
+    #[default]
+    Even,
+    /// The request is split in proportion to one weight per aggregate, in SELECT order.
+    Weighted { weights: Vec<f64> },
+    /// Every aggregate is noised with the full request, the query costs it once per aggregate
+    /// and once more for partition selection.
+    PerAggregate,
+}
+
+impl BudgetAllocation {
+    /// Sets aside what partition selection of a grouped query with `aggregates` aggregates
+    /// is run with, an even share next to the aggregates or the full request under
+    /// `PerAggregate`.
+    ///
+    /// # Returns
+    /// What is left of `requested` for the aggregates and the share of the selection.
+    pub fn set_aside_selection(
+        &self,
+        requested: PrivacyBudget,
+        aggregates: usize,
+    ) -> (PrivacyBudget, PrivacyBudget) {
+        if *self == BudgetAllocation::PerAggregate {
+            return (requested, requested);
+        }
+        let share = 1.0 / (aggregates + 1) as f64;
+        (
+            PrivacyBudget::new(
+                requested.epsilon * (1.0 - share),
+                requested.delta * (1.0 - share),
+            ),
+            PrivacyBudget::new(requested.epsilon * share, requested.delta * share),
+        )
+    }
+
+    /// The (epsilon, delta) each of `aggregates` aggregates is noised with.
+    ///
+    /// # Errors
+    /// Returns an error if the weights do not match the aggregates or are not all positive.
+    pub fn split(
+        &self,
+        requested: PrivacyBudget,
+        aggregates: usize,
+    ) -> Result<Vec<PrivacyBudget>, String> {
+        let shares: Vec<f64> = match self {
+            BudgetAllocation::Even => vec![1.0 / aggregates as f64; aggregates],
+            BudgetAllocation::PerAggregate => vec![1.0; aggregates],
+            BudgetAllocation::Weighted { weights } => {
+                if weights.len() != aggregates {
+                    return Err(format!(
+                        "Expected {aggregates} weights, one per aggregate, got {}",
+                        weights.len()
+                    ));
+                }
+                if weights
+                    .iter()
+                    .any(|weight| !weight.is_finite() || *weight <= 0.0)
+                {
+                    return Err("Budget weights must be positive".to_string());
+                }
+                let total: f64 = weights.iter().sum();
+                weights.iter().map(|weight| weight / total).collect()
+            }
+        };
+        Ok(shares
+            .iter()
+            .map(|share| PrivacyBudget::new(requested.epsilon * share, requested.delta * share))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BudgetAllocation;
+    use crate::database::schema::PrivacyBudget;
+
+    fn epsilons(allocation: BudgetAllocation, aggregates: usize) -> Vec<f64> {
+        allocation
+            .split(PrivacyBudget::new(3.0, 0.0), aggregates)
+            .unwrap()
+            .iter()
+            .map(|share| share.epsilon)
+            .collect()
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(
+            [1.0, 1.0, 1.0].to_vec(),
+            epsilons(BudgetAllocation::Even, 3)
+        );
+        assert_eq!(
+            [3.0, 3.0].to_vec(),
+            epsilons(BudgetAllocation::PerAggregate, 2)
+        );
+        let weighted = BudgetAllocation::Weighted {
+            weights: vec![1.0, 2.0],
+        };
+        assert_eq!([1.0, 2.0].to_vec(), epsilons(weighted.clone(), 2));
+        assert!(weighted.split(PrivacyBudget::new(3.0, 0.0), 3).is_err());
+        assert!(BudgetAllocation::Weighted {
+            weights: vec![1.0, 0.0]
+        }
+        .split(PrivacyBudget::new(3.0, 0.0), 2)
+        .is_err());
+    }
+
+    #[test]
+    fn selection_share() {
+        let requested = PrivacyBudget::new(3.0, 3e-6);
+        let (aggregates, selection) = BudgetAllocation::Even.set_aside_selection(requested, 2);
+        assert_eq!(2.0, aggregates.epsilon);
+        assert_eq!(1.0, selection.epsilon);
+        assert!((aggregates.delta + selection.delta - requested.delta).abs() < 1e-18);
+        assert_eq!(
+            (requested, requested),
+            BudgetAllocation::PerAggregate.set_aside_selection(requested, 2)
+        );
+    }
+}
@@ -1,73 +1,697 @@
+use crate::database::database::SupportedDatabases;
+use crate::database::identifiers::IdentifierRules;
+use sqlparser::ast::{
+    BinaryOperator, DuplicateTreatment, Expr, FunctionArg, FunctionArgExpr, FunctionArguments,
+    GroupByExpr, JoinConstraint, JoinOperator, Query, Select, SelectItem, SetExpr, Statement,
+    TableFactor, TableWithJoins, Visit, Visitor,
+};
+use sqlparser::dialect::{Dialect, GenericDialect, MySqlDialect, SQLiteDialect};
+use sqlparser::parser::Parser;
+use std::fmt;
+use std::ops::ControlFlow;
+
+/// Functions that aggregate over rows, any other function is treated as a scalar function.
+static AGGREGATE_FUNCTIONS: [&str; 9] = [
+    "sum",
+    "avg",
+    "count",
+    "min",
+    "max",
+    "median",
+    "percentile",
+    "variance",
+    "stddev",
+];
+
 pub struct SqlAnalyzer {
     pub sql: String,
+    /// The backend the query is written for, `None` parses any common SQL.
+    flavour: Option<SupportedDatabases>,
+    identifiers: IdentifierRules,
 }
 
-impl SqlAnalyzer {
-    pub fn new(sql: &str) -> Self {
-        SqlAnalyzer {
-            sql: sql.to_ascii_lowercase().trim_end().to_string(),
+/// A reference to a column, optionally qualified with a table name or alias.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ColumnRef {
+    pub table: Option<String>,
+    pub name: String,
+}
+
+impl ColumnRef {
+    fn normalize(&mut self, identifiers: &IdentifierRules) {
+        self.table = self.table.as_deref().map(|table| identifiers.table(table));
+        self.name = identifiers.column(&self.name);
+    }
+}
+
+impl fmt::Display for ColumnRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.table {
+            Some(table) => write!(f, "{}.{}", table, self.name),
+            None => write!(f, "{}", self.name),
         }
     }
+}
 
-    fn clean_results(&self, results: Vec<String>) -> Vec<String> {
-        results
-            .iter()
-            .map(|r: &String| {
-                r.trim_end()
-                    .replace(" ", "")
-                    .replace(",", "")
-                    .replace(";", "")
-            })
-            .collect::<Vec<String>>()
+/// An expression of the query together with the columns it references,
+/// columns referenced inside subqueries belong to the subquery.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub text: String,
+    pub columns: Vec<ColumnRef>,
+}
+
+impl Expression {
+    fn normalize(&mut self, identifiers: &IdentifierRules) {
+        for column in self.columns.iter_mut() {
+            column.normalize(identifiers);
+        }
     }
+}
 
-    pub fn is_read(&self) -> bool {
-        self.sql.starts_with("select")
+/// An aggregate function call, e.g. `sum(age + 1)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregate {
+    /// The lowercased function name.
+    pub function: String,
+    pub distinct: bool,
+    /// The arguments of the call, empty for `count(*)`.
+    pub arguments: Vec<Expression>,
+}
+
+/// An item of the SELECT list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projection {
+    pub expression: Expression,
+    pub alias: Option<String>,
+    /// Set if the whole expression is a single aggregate call.
+    pub aggregate: Option<Aggregate>,
+    /// Columns referenced outside of any aggregate call, their row-level values end up in the result.
+    pub row_level_columns: Vec<ColumnRef>,
+    /// `*` or `table.*`.
+    pub wildcard: bool,
+    /// Set if the whole expression is a single column reference.
+    pub column: Option<ColumnRef>,
+}
+
+impl Projection {
+    /// The name the projection is returned under by the database.
+    pub fn label(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.expression.text)
     }
 
-    pub fn tables_from_sql(&self) -> Vec<String> {
-        let mut tables: Vec<String> = vec![];
-        let parts = self.sql.split_whitespace().collect::<Vec<&str>>();
-        let terminals: Vec<&str> = vec!["where", "group", "having", "order", "on"];
+    fn normalize(&mut self, identifiers: &IdentifierRules) {
+        self.expression.normalize(identifiers);
+        if let Some(aggregate) = self.aggregate.as_mut() {
+            for argument in aggregate.arguments.iter_mut() {
+                argument.normalize(identifiers);
+            }
+        }
+        for column in self
+            .row_level_columns
+            .iter_mut()
+            .chain(self.column.as_mut())
+        {
+            column.normalize(identifiers);
+        }
+    }
+}
+
+/// A relation in the FROM clause or in a JOIN.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Relation {
+    Table {
+        name: String,
+        alias: Option<String>,
+    },
+    Subquery {
+        query: Box<QueryDescription>,
+        alias: Option<String>,
+    },
+}
+
+impl Relation {
+    fn normalize(&mut self, identifiers: &IdentifierRules) {
+        match self {
+            Relation::Table { name, alias } => {
+                *name = identifiers.table(name);
+                *alias = alias.as_deref().map(|alias| identifiers.table(alias));
+            }
+            Relation::Subquery { query, alias } => {
+                query.normalize(identifiers);
+                *alias = alias.as_deref().map(|alias| identifiers.table(alias));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Right,
+    Full,
+    Cross,
+    Other,
+}
 
-        let position = parts
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub relation: Relation,
+    pub kind: JoinKind,
+    /// The ON or USING condition, if any.
+    pub constraint: Option<Expression>,
+    /// The column equalities the condition requires of every joined row, `a.x = b.y`
+    /// terms of an ON condition that are ANDed together. The columns of a USING condition
+    /// are unqualified on both sides.
+    pub equalities: Vec<(ColumnRef, ColumnRef)>,
+}
+
+/// The structure of a SELECT query as far as differential privacy is concerned.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QueryDescription {
+    pub projections: Vec<Projection>,
+    pub from: Vec<Relation>,
+    pub joins: Vec<Join>,
+    pub selection: Option<Expression>,
+    pub group_by: Vec<Expression>,
+    pub having: Option<Expression>,
+    pub order_by: Vec<Expression>,
+    /// The LIMIT, OFFSET and FETCH clauses as written, if any.
+    pub limit: Option<String>,
+    /// Subqueries in expressions and common table expressions.
+    pub subqueries: Vec<QueryDescription>,
+}
+
+impl QueryDescription {
+    /// Names of all tables read by the query, including the ones read by subqueries.
+    pub fn tables(&self) -> Vec<String> {
+        let mut tables: Vec<String> = vec![];
+        let mut add = |names: Vec<String>| {
+            for name in names {
+                if !tables.contains(&name) {
+                    tables.push(name);
+                }
+            }
+        };
+        let relations = self
+            .from
             .iter()
-            .position(|&r| r == "from")
-            .expect("Invalid read sql");
+            .chain(self.joins.iter().map(|join| &join.relation));
+        for relation in relations {
+            match relation {
+                Relation::Table { name, .. } => add(vec![name.to_owned()]),
+                Relation::Subquery { query, .. } => add(query.tables()),
+            }
+        }
+        for subquery in self.subqueries.iter() {
+            add(subquery.tables());
+        }
+        tables
+    }
 
-        for index in position + 1..parts.len() {
-            if terminals.contains(&parts[index]) {
-                break;
+    /// Brings every table and column reference into the form `identifiers` compares them in.
+    fn normalize(&mut self, identifiers: &IdentifierRules) {
+        for projection in self.projections.iter_mut() {
+            projection.normalize(identifiers);
+        }
+        for relation in self.from.iter_mut() {
+            relation.normalize(identifiers);
+        }
+        for join in self.joins.iter_mut() {
+            join.relation.normalize(identifiers);
+            if let Some(constraint) = join.constraint.as_mut() {
+                constraint.normalize(identifiers);
             }
-            if parts[index] != "join" {
-                tables.push(parts[index].to_string());
+            for (left, right) in join.equalities.iter_mut() {
+                left.normalize(identifiers);
+                right.normalize(identifiers);
             }
         }
+        let expressions = self
+            .selection
+            .iter_mut()
+            .chain(self.group_by.iter_mut())
+            .chain(self.having.iter_mut())
+            .chain(self.order_by.iter_mut());
+        for expression in expressions {
+            expression.normalize(identifiers);
+        }
+        for subquery in self.subqueries.iter_mut() {
+            subquery.normalize(identifiers);
+        }
+    }
+}
 
-        // Handling alises is challening in this case, however we can do that when we run the SQL query.
-        self.clean_results(tables)
+/// Collects the columns referenced by an expression, keeping track of whether
+/// they sit inside an aggregate call or a subquery.
+#[derive(Default)]
+struct ColumnCollector {
+    columns: Vec<ColumnRef>,
+    row_level_columns: Vec<ColumnRef>,
+    subqueries: Vec<Query>,
+    aggregate_depth: usize,
+    query_depth: usize,
+}
+
+fn is_aggregate_call(expr: &Expr) -> bool {
+    match expr {
+        // Window functions return a value per row, they are not aggregates.
+        Expr::Function(function) if function.over.is_none() => {
+            let name = function
+                .name
+                .0
+                .last()
+                .map(|ident| ident.value.to_ascii_lowercase())
+                .unwrap_or_default();
+            AGGREGATE_FUNCTIONS.contains(&name.as_str())
+        }
+        _ => false,
     }
+}
 
-    /// List all the columns that are being used
-    pub fn columns_from_sql(&self) -> Vec<String> {
-        let mut columns: Vec<String> = vec![];
-        let parts = self.sql.split_whitespace().collect::<Vec<&str>>();
-        let mut index = 1;
-        while index < parts.len() {
-            if parts[index] == "from" {
-                break;
+fn column_ref(expr: &Expr) -> Option<ColumnRef> {
+    match expr {
+        Expr::Identifier(ident) => Some(ColumnRef {
+            table: None,
+            name: ident.value.to_owned(),
+        }),
+        Expr::CompoundIdentifier(idents) if idents.len() >= 2 => Some(ColumnRef {
+            table: Some(idents[idents.len() - 2].value.to_owned()),
+            name: idents[idents.len() - 1].value.to_owned(),
+        }),
+        _ => None,
+    }
+}
+
+/// Collects the `a = b` column equalities of a join condition that hold for every joined
+/// row, the ones under an OR or NOT may not.
+fn column_equalities(expr: &Expr, equalities: &mut Vec<(ColumnRef, ColumnRef)>) {
+    match expr {
+        Expr::Nested(inner) => column_equalities(inner, equalities),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            column_equalities(left, equalities);
+            column_equalities(right, equalities);
+        }
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+        } => {
+            if let (Some(left), Some(right)) = (column_ref(left), column_ref(right)) {
+                equalities.push((left, right));
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Visitor for ColumnCollector {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        if self.query_depth == 0 {
+            self.subqueries.push(query.clone());
+        }
+        self.query_depth += 1;
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<Self::Break> {
+        self.query_depth -= 1;
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        if self.query_depth > 0 {
+            return ControlFlow::Continue(());
+        }
+        if is_aggregate_call(expr) {
+            self.aggregate_depth += 1;
+        }
+        if let Some(column) = column_ref(expr) {
+            if self.aggregate_depth == 0 {
+                self.row_level_columns.push(column.clone());
             }
-            columns.push(parts[index].to_owned());
-            index += 1;
+            self.columns.push(column);
         }
-        self.clean_results(columns)
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        if self.query_depth == 0 && is_aggregate_call(expr) {
+            self.aggregate_depth -= 1;
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Describes queries in the order they are encountered, subqueries found in expressions
+/// are described recursively.
+struct Describer {
+    subqueries: Vec<QueryDescription>,
+}
+
+fn collect_columns(expr: &Expr) -> ColumnCollector {
+    let mut collector = ColumnCollector::default();
+    let _ = expr.visit(&mut collector);
+    collector
+}
+
+impl Describer {
+    fn collect(&mut self, expr: &Expr) -> Result<ColumnCollector, String> {
+        let collector = collect_columns(expr);
+        for subquery in collector.subqueries.iter() {
+            self.subqueries.push(describe_query(subquery)?);
+        }
+        Ok(collector)
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<Expression, String> {
+        let collector = self.collect(expr)?;
+        Ok(Expression {
+            text: expr.to_string(),
+            columns: collector.columns,
+        })
+    }
+
+    /// The aggregate call `expr` consists of, its subqueries are collected with the projection.
+    fn aggregate(expr: &Expr) -> Option<Aggregate> {
+        let expr = match expr {
+            Expr::Nested(inner) => return Describer::aggregate(inner),
+            Expr::Function(function) if is_aggregate_call(expr) => function,
+            _ => return None,
+        };
+        let (distinct, args) = match &expr.args {
+            FunctionArguments::List(list) => (
+                list.duplicate_treatment == Some(DuplicateTreatment::Distinct),
+                list.args.iter().collect::<Vec<&FunctionArg>>(),
+            ),
+            _ => (false, vec![]),
+        };
+        let mut arguments: Vec<Expression> = vec![];
+        for arg in args {
+            match arg {
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(arg))
+                | FunctionArg::Named {
+                    arg: FunctionArgExpr::Expr(arg),
+                    ..
+                } => arguments.push(Expression {
+                    text: arg.to_string(),
+                    columns: collect_columns(arg).columns,
+                }),
+                // `count(*)` and `count(table.*)` have no column argument.
+                _ => {}
+            }
+        }
+        Some(Aggregate {
+            function: expr.name.to_string().to_ascii_lowercase(),
+            distinct,
+            arguments,
+        })
+    }
+
+    fn projection(&mut self, item: &SelectItem) -> Result<Projection, String> {
+        let (expr, alias) = match item {
+            SelectItem::UnnamedExpr(expr) => (expr, None),
+            SelectItem::ExprWithAlias { expr, alias } => (expr, Some(alias.value.to_owned())),
+            SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(_, _) => {
+                return Ok(Projection {
+                    expression: Expression {
+                        text: item.to_string(),
+                        columns: vec![],
+                    },
+                    alias: None,
+                    aggregate: None,
+                    row_level_columns: vec![],
+                    wildcard: true,
+                    column: None,
+                })
+            }
+        };
+        let collector = self.collect(expr)?;
+        Ok(Projection {
+            expression: Expression {
+                text: expr.to_string(),
+                columns: collector.columns,
+            },
+            alias,
+            aggregate: Describer::aggregate(expr),
+            row_level_columns: collector.row_level_columns,
+            wildcard: false,
+            column: column_ref(expr),
+        })
+    }
+
+    fn relation(&mut self, table_factor: &TableFactor) -> Result<Relation, String> {
+        match table_factor {
+            TableFactor::Table { name, alias, .. } => Ok(Relation::Table {
+                name: name
+                    .0
+                    .last()
+                    .map(|ident| ident.value.to_owned())
+                    .unwrap_or_default(),
+                alias: alias.as_ref().map(|alias| alias.name.value.to_owned()),
+            }),
+            TableFactor::Derived {
+                subquery, alias, ..
+            } => Ok(Relation::Subquery {
+                query: Box::new(describe_query(subquery)?),
+                alias: alias.as_ref().map(|alias| alias.name.value.to_owned()),
+            }),
+            _ => Err(format!("Unsupported table expression: {table_factor}")),
+        }
+    }
+
+    fn from(
+        &mut self,
+        from: &TableWithJoins,
+        description: &mut QueryDescription,
+    ) -> Result<(), String> {
+        match &from.relation {
+            // `FROM (a JOIN b)` is flattened into the outer FROM.
+            TableFactor::NestedJoin {
+                table_with_joins, ..
+            } => self.from(table_with_joins, description)?,
+            relation => description.from.push(self.relation(relation)?),
+        }
+        for join in from.joins.iter() {
+            let (kind, constraint) = match &join.join_operator {
+                JoinOperator::Inner(constraint) => (JoinKind::Inner, Some(constraint)),
+                JoinOperator::LeftOuter(constraint) => (JoinKind::Left, Some(constraint)),
+                JoinOperator::RightOuter(constraint) => (JoinKind::Right, Some(constraint)),
+                JoinOperator::FullOuter(constraint) => (JoinKind::Full, Some(constraint)),
+                JoinOperator::CrossJoin => (JoinKind::Cross, None),
+                _ => (JoinKind::Other, None),
+            };
+            let mut equalities: Vec<(ColumnRef, ColumnRef)> = vec![];
+            let constraint = match constraint {
+                Some(JoinConstraint::On(expr)) => {
+                    column_equalities(expr, &mut equalities);
+                    Some(self.expression(expr)?)
+                }
+                Some(JoinConstraint::Using(idents)) => {
+                    let columns: Vec<ColumnRef> = idents
+                        .iter()
+                        .map(|ident| ColumnRef {
+                            table: None,
+                            name: ident.value.to_owned(),
+                        })
+                        .collect();
+                    equalities.extend(
+                        columns
+                            .iter()
+                            .map(|column| (column.to_owned(), column.to_owned())),
+                    );
+                    Some(Expression {
+                        text: format!(
+                            "USING({})",
+                            idents
+                                .iter()
+                                .map(|ident| ident.value.as_str())
+                                .collect::<Vec<&str>>()
+                                .join(", ")
+                        ),
+                        columns,
+                    })
+                }
+                _ => None,
+            };
+            description.joins.push(Join {
+                relation: self.relation(&join.relation)?,
+                kind,
+                constraint,
+                equalities,
+            });
+        }
+        Ok(())
+    }
+
+    fn select(&mut self, select: &Select) -> Result<QueryDescription, String> {
+        let mut description = QueryDescription::default();
+        for from in select.from.iter() {
+            self.from(from, &mut description)?;
+        }
+        for item in select.projection.iter() {
+            let projection = self.projection(item)?;
+            description.projections.push(projection);
+        }
+        if let Some(selection) = &select.selection {
+            description.selection = Some(self.expression(selection)?);
+        }
+        match &select.group_by {
+            GroupByExpr::Expressions(exprs) => {
+                for expr in exprs.iter() {
+                    let expression = self.expression(expr)?;
+                    description.group_by.push(expression);
+                }
+            }
+            GroupByExpr::All => return Err("GROUP BY ALL is not supported".to_string()),
+        }
+        if let Some(having) = &select.having {
+            description.having = Some(self.expression(having)?);
+        }
+        Ok(description)
+    }
+}
+
+/// Records the ORDER BY and LIMIT clauses of `query` in `description`.
+fn describe_ordering(query: &Query, description: &mut QueryDescription) -> Result<(), String> {
+    let mut describer = Describer { subqueries: vec![] };
+    for order_by in query.order_by.iter() {
+        let expression = describer.expression(&order_by.expr)?;
+        description.order_by.push(expression);
+    }
+    let limit: Vec<String> = query
+        .limit
+        .iter()
+        .map(|limit| format!("LIMIT {limit}"))
+        .chain(query.offset.iter().map(|offset| offset.to_string()))
+        .chain(query.fetch.iter().map(|fetch| fetch.to_string()))
+        .collect();
+    if !limit.is_empty() {
+        description.limit = Some(limit.join(" "));
+    }
+    description.subqueries.extend(describer.subqueries);
+    Ok(())
+}
+
+fn describe_query(query: &Query) -> Result<QueryDescription, String> {
+    let select = match query.body.as_ref() {
+        SetExpr::Select(select) => select,
+        SetExpr::Query(inner) => {
+            let mut description = describe_query(inner)?;
+            describe_ordering(query, &mut description)?;
+            return Ok(description);
+        }
+        SetExpr::SetOperation { .. } => {
+            return Err("Set operations (UNION, INTERSECT, EXCEPT) are not supported".to_string())
+        }
+        body => return Err(format!("Unsupported query: {body}")),
+    };
+    let mut describer = Describer { subqueries: vec![] };
+    if let Some(with) = &query.with {
+        for cte in with.cte_tables.iter() {
+            describer.subqueries.push(describe_query(&cte.query)?);
+        }
+    }
+    let mut description = describer.select(select)?;
+    description.subqueries = describer.subqueries;
+    describe_ordering(query, &mut description)?;
+    Ok(description)
+}
+
+impl SqlAnalyzer {
+    /// An analyzer for SQL that is not tied to a backend, identifiers are compared ignoring case.
+    pub fn new(sql: &str) -> Self {
+        SqlAnalyzer {
+            sql: sql.trim_end().to_string(),
+            flavour: None,
+            identifiers: IdentifierRules::default(),
+        }
+    }
+
+    /// An analyzer that parses `sql` in the dialect of `flavour` and normalizes
+    /// its identifiers following `identifiers`.
+    pub fn for_backend(
+        sql: &str,
+        flavour: SupportedDatabases,
+        identifiers: IdentifierRules,
+    ) -> Self {
+        SqlAnalyzer {
+            sql: sql.trim_end().to_string(),
+            flavour: Some(flavour),
+            identifiers,
+        }
+    }
+
+    fn parse(&self) -> Result<Vec<Statement>, String> {
+        let dialect: Box<dyn Dialect> = match self.flavour {
+            Some(SupportedDatabases::MySQL) => Box::new(MySqlDialect {}),
+            Some(SupportedDatabases::SQLite) => Box::new(SQLiteDialect {}),
+            None => Box::new(GenericDialect {}),
+        };
+        Parser::parse_sql(dialect.as_ref(), &self.sql).map_err(|e| e.to_string())
+    }
+
+    pub fn is_read(&self) -> bool {
+        matches!(self.parse().as_deref(), Ok([Statement::Query(_)]))
+    }
+
+    /// Parses the query into a `QueryDescription`, table and column references are normalized.
+    ///
+    /// # Errors
+    /// Returns an error if the SQL does not parse or is not a single SELECT query.
+    pub fn describe(&self) -> Result<QueryDescription, String> {
+        let mut description = describe_query(&self.query()?)?;
+        description.normalize(&self.identifiers);
+        Ok(description)
+    }
+
+    /// Parses the query into its syntax tree, e.g. to rewrite it.
+    ///
+    /// # Errors
+    /// Returns an error if the SQL does not parse or is not a single SELECT query.
+    pub fn query(&self) -> Result<Query, String> {
+        let mut statements = self.parse()?;
+        match (statements.pop(), statements.is_empty()) {
+            (Some(Statement::Query(query)), true) => Ok(*query),
+            (Some(_), true) => Err("Only SELECT queries are supported".to_string()),
+            _ => Err("Expected exactly one SQL statement".to_string()),
+        }
+    }
+
+    /// Names of all tables read by the query, empty if the query does not parse.
+    pub fn tables_from_sql(&self) -> Vec<String> {
+        self.describe()
+            .map(|description| description.tables())
+            .unwrap_or_default()
+    }
+
+    /// List all the columns that are being used
+    pub fn columns_from_sql(&self) -> Vec<String> {
+        self.describe()
+            .map(|description| {
+                description
+                    .projections
+                    .iter()
+                    .map(|projection| projection.expression.text.to_owned())
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 }
 
 // Ok so this ensures that tests are only compiled when we run test
 #[cfg(test)]
 mod tests {
-    use super::SqlAnalyzer;
+    use super::{ColumnRef, JoinKind, Relation, SqlAnalyzer};
+    use crate::database::database::SupportedDatabases;
+    use crate::database::identifiers::IdentifierRules;
 
     #[test]
     fn is_read() {
@@ -84,10 +708,7 @@ mod tests {
         assert_eq!(["users", "models"].to_vec(), analyser.tables_from_sql());
 
         let analyser = SqlAnalyzer::new("SELECT * FROM USERS u, MODELS m;");
-        assert_eq!(
-            ["users", "u", "models", "m"].to_vec(),
-            analyser.tables_from_sql()
-        );
+        assert_eq!(["users", "models"].to_vec(), analyser.tables_from_sql());
     }
 
     #[test]
@@ -100,7 +721,7 @@ mod tests {
 
         let analyser = SqlAnalyzer::new("SELECT Avg(Name), Sum(Age) FROM USERS, MODELS;");
         assert_eq!(
-            ["avg(name)", "sum(age)"].to_vec(),
+            ["Avg(Name)", "Sum(Age)"].to_vec(),
             analyser.columns_from_sql()
         );
     }
@@ -127,13 +748,174 @@ mod tests {
         );
         assert_eq!(
             [
-                "employees.employeeid",
-                "employees.firstname",
-                "employees.lastname",
-                "departments.departmentname"
+                "Employees.EmployeeID",
+                "Employees.FirstName",
+                "Employees.LastName",
+                "Departments.DepartmentName"
             ]
             .to_vec(),
             analyzer.columns_from_sql()
         )
     }
+
+    #[test]
+    fn join_equalities() {
+        let description = SqlAnalyzer::new(
+            "SELECT COUNT(*) FROM users u JOIN visits v ON (u.id = v.user_id AND v.page > 1)
+            JOIN pages USING (page) LEFT JOIN models m ON u.id = m.owner OR m.owner IS NULL;",
+        )
+        .describe()
+        .unwrap();
+        let column = |table: Option<&str>, name: &str| ColumnRef {
+            table: table.map(str::to_string),
+            name: name.to_string(),
+        };
+        assert_eq!(
+            vec![(column(Some("u"), "id"), column(Some("v"), "user_id"))],
+            description.joins[0].equalities
+        );
+        assert_eq!(
+            vec![(column(None, "page"), column(None, "page"))],
+            description.joins[1].equalities
+        );
+        // Rows joined through the OR need not have equal keys.
+        assert_eq!(JoinKind::Left, description.joins[2].kind);
+        assert!(description.joins[2].equalities.is_empty());
+    }
+
+    #[test]
+    fn aggregates() {
+        let description = SqlAnalyzer::new(
+            "SELECT sum(age + 1), count( age ) AS total, count(*), age FROM users;",
+        )
+        .describe()
+        .unwrap();
+        let projections = description.projections;
+
+        let sum = projections[0].aggregate.as_ref().unwrap();
+        assert_eq!("sum", sum.function);
+        assert_eq!("age + 1", sum.arguments[0].text);
+        assert!(projections[0].row_level_columns.is_empty());
+
+        assert_eq!("count", projections[1].aggregate.as_ref().unwrap().function);
+        assert_eq!("total", projections[1].label());
+        assert_eq!("count(age)", projections[1].expression.text);
+
+        assert!(projections[2]
+            .aggregate
+            .as_ref()
+            .unwrap()
+            .arguments
+            .is_empty());
+
+        assert!(projections[3].aggregate.is_none());
+        assert_eq!(
+            vec![ColumnRef {
+                table: None,
+                name: "age".to_string()
+            }],
+            projections[3].row_level_columns
+        );
+
+        let description = SqlAnalyzer::new("SELECT MEDIAN(age), percentile(age, 0.9) FROM users;")
+            .describe()
+            .unwrap();
+        let median = description.projections[0].aggregate.as_ref().unwrap();
+        assert_eq!("median", median.function);
+        let percentile = description.projections[1].aggregate.as_ref().unwrap();
+        assert_eq!("percentile", percentile.function);
+        assert_eq!("0.9", percentile.arguments[1].text);
+        assert!(percentile.arguments[1].columns.is_empty());
+        assert!(description.projections[1].row_level_columns.is_empty());
+    }
+
+    #[test]
+    fn joins_and_clauses() {
+        let description = SqlAnalyzer::new(
+            "SELECT d.name, avg(e.salary) FROM employees e
+            LEFT JOIN departments AS d ON e.department_id = d.id
+            WHERE e.age > 30 GROUP BY d.name HAVING count(e.id) > 5;",
+        )
+        .describe()
+        .unwrap();
+        assert_eq!(
+            Relation::Table {
+                name: "employees".to_string(),
+                alias: Some("e".to_string())
+            },
+            description.from[0]
+        );
+        assert_eq!(JoinKind::Left, description.joins[0].kind);
+        assert_eq!(
+            2,
+            description.joins[0]
+                .constraint
+                .as_ref()
+                .unwrap()
+                .columns
+                .len()
+        );
+        assert_eq!("e.age > 30", description.selection.unwrap().text);
+        assert_eq!("d.name", description.group_by[0].text);
+        assert_eq!("count(e.id) > 5", description.having.unwrap().text);
+    }
+
+    #[test]
+    fn ordering() {
+        let description = SqlAnalyzer::new(
+            "SELECT city, count(age) FROM users GROUP BY city ORDER BY count(age) DESC LIMIT 1 OFFSET 2;",
+        )
+        .describe()
+        .unwrap();
+        assert_eq!("count(age)", description.order_by[0].text);
+        assert_eq!(Some("LIMIT 1 OFFSET 2"), description.limit.as_deref());
+    }
+
+    #[test]
+    fn subqueries() {
+        let analyzer = SqlAnalyzer::new(
+            "SELECT count(t.age) FROM (SELECT age FROM users) t
+            WHERE t.age IN (SELECT age FROM models);",
+        );
+        let description = analyzer.describe().unwrap();
+        assert_eq!(1, description.subqueries.len());
+        assert_eq!(
+            vec![ColumnRef {
+                table: Some("t".to_string()),
+                name: "age".to_string()
+            }],
+            description.selection.unwrap().columns
+        );
+        assert_eq!(["users", "models"].to_vec(), analyzer.tables_from_sql());
+    }
+
+    #[test]
+    fn identifiers() {
+        let description = SqlAnalyzer::for_backend(
+            "SELECT SUM(`Age`) FROM `Users` U WHERE Name = 'Bob';",
+            SupportedDatabases::MySQL,
+            IdentifierRules::mysql(0),
+        )
+        .describe()
+        .unwrap();
+        assert_eq!(
+            vec![Relation::Table {
+                name: "Users".to_string(),
+                alias: Some("U".to_string())
+            }],
+            description.from
+        );
+        let aggregate = description.projections[0].aggregate.as_ref().unwrap();
+        assert_eq!("age", aggregate.arguments[0].columns[0].name);
+        assert_eq!("Name = 'Bob'", description.selection.unwrap().text);
+
+        let description = SqlAnalyzer::for_backend(
+            "SELECT sum(\"Age\") FROM Users;",
+            SupportedDatabases::SQLite,
+            IdentifierRules::sqlite(),
+        )
+        .describe()
+        .unwrap();
+        assert_eq!(["users"].to_vec(), description.tables());
+    }
 }
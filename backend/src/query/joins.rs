@@ -0,0 +1,235 @@
+use crate::query::analyzer::{JoinKind, QueryDescription, Relation};
+use crate::query::resolver::Resolver;
+use crate::transforms::elastic::JoinStep;
+use std::collections::HashMap;
+
+/// A column two tables are joined on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JoinKey {
+    pub table: String,
+    pub column: String,
+}
+
+/// An inner equi-join of a relation onto the relations before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EquiJoin {
+    /// The position of the relation `left_key` is read from, see `JoinStep::left`.
+    pub left: usize,
+    pub left_key: JoinKey,
+    /// The key of the joined relation.
+    pub right_key: JoinKey,
+    /// Whether the joined relation reads a table already read on the left.
+    pub self_join: bool,
+}
+
+impl EquiJoin {
+    /// The join as far as its stability is concerned, with the max frequencies of its keys.
+    pub fn step(&self, frequencies: &HashMap<JoinKey, f64>) -> JoinStep {
+        let frequency = |key: &JoinKey| frequencies.get(key).copied().unwrap_or_default();
+        JoinStep {
+            left: self.left,
+            left_frequency: frequency(&self.left_key),
+            right_frequency: frequency(&self.right_key),
+            self_join: self.self_join,
+        }
+    }
+}
+
+fn relation_name(relation: &Relation) -> &str {
+    match relation {
+        Relation::Table { name, alias } => alias.as_deref().unwrap_or(name),
+        Relation::Subquery { alias, .. } => alias.as_deref().unwrap_or("a subquery"),
+    }
+}
+
+/// Whether a subquery or derived table of `description` joins tables of its own.
+fn has_nested_joins(description: &QueryDescription) -> bool {
+    let joins = |query: &QueryDescription| {
+        !query.joins.is_empty() || query.from.len() > 1 || has_nested_joins(query)
+    };
+    let derived = description
+        .from
+        .iter()
+        .chain(description.joins.iter().map(|join| &join.relation))
+        .any(|relation| match relation {
+            Relation::Subquery { query, .. } => joins(query),
+            Relation::Table { .. } => false,
+        });
+    derived || description.subqueries.iter().any(joins)
+}
+
+/// The joins of the query as a sequence of inner equi-joins, the noise of a joined
+/// aggregate is calibrated to the max frequencies of their keys.
+///
+/// A join is bounded by the first of its equalities between a column of the joined table
+/// and a column of a table before it, its other conditions only drop rows.
+///
+/// # Returns
+/// The joins in the order they are made, empty if the query reads a single relation.
+///
+/// # Errors
+/// Returns an error if a row can match every row of another table, for cross joins,
+/// implicit joins and joins without an equality, and for outer joins and joins of
+/// subqueries, which are not supported.
+pub fn equi_joins(
+    description: &QueryDescription,
+    resolver: &Resolver,
+) -> Result<Vec<EquiJoin>, String> {
+    if has_nested_joins(description) {
+        return Err("Joins are only supported in the outermost query".to_string());
+    }
+    if let [first, second, ..] = description.from.as_slice() {
+        return Err(format!(
+            "FROM {}, {} is an unbounded join, join the tables ON equal columns",
+            relation_name(first),
+            relation_name(second)
+        ));
+    }
+    let mut joins: Vec<EquiJoin> = vec![];
+    let mut read: Vec<String> = vec![];
+    for (position, join) in (1..).zip(description.joins.iter()) {
+        let name = relation_name(&join.relation);
+        match join.kind {
+            JoinKind::Inner => {}
+            JoinKind::Left | JoinKind::Right | JoinKind::Full => {
+                return Err(format!(
+                    "The outer join with {name} is not supported, only inner joins are"
+                ))
+            }
+            JoinKind::Cross | JoinKind::Other => {
+                return Err(format!(
+                    "The join with {name} is unbounded, join the tables ON equal columns"
+                ))
+            }
+        }
+        if let Relation::Subquery { .. } = join.relation {
+            return Err(format!(
+                "The join with {name} is not supported, it reads a subquery"
+            ));
+        }
+        let keys = join.equalities.iter().find_map(|(a, b)| {
+            let key = |left: &_, right: &_| {
+                Some((
+                    resolver.table_column(left, 0..position)?,
+                    resolver.table_column(right, position..position + 1)?,
+                ))
+            };
+            key(a, b).or_else(|| key(b, a))
+        });
+        let Some(((left, left_table, left_column), (_, right_table, right_column))) = keys else {
+            return Err(format!(
+                "The join with {name} is unbounded, join it ON columns equal to columns \
+                of the tables before it"
+            ));
+        };
+        if read.is_empty() {
+            read.push(left_table.name.to_owned());
+        }
+        joins.push(EquiJoin {
+            left,
+            left_key: JoinKey {
+                table: left_table.name.to_owned(),
+                column: left_column.name,
+            },
+            right_key: JoinKey {
+                table: right_table.name.to_owned(),
+                column: right_column.name,
+            },
+            self_join: read.contains(&right_table.name),
+        });
+        read.push(right_table.name.to_owned());
+    }
+    Ok(joins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{equi_joins, EquiJoin, JoinKey};
+    use crate::database::identifiers::IdentifierRules;
+    use crate::database::schema::{Column, PrivacyBudget, Table};
+    use crate::query::analyzer::SqlAnalyzer;
+    use crate::query::resolver::Resolver;
+
+    fn table(name: &str, columns: &[&str]) -> Table {
+        Table {
+            name: name.to_string(),
+            columns: columns
+                .iter()
+                .map(|column| Column {
+                    name: column.to_string(),
+                    ctype: "Integer".to_string(),
+                    sensitivity: 1.0,
+                    bounds: None,
+                    usage: None,
+                    table_name: name.to_string(),
+                })
+                .collect(),
+            privacy_budget: PrivacyBudget::default(),
+            privacy_unit: None,
+        }
+    }
+
+    fn joins(query: &str) -> Result<Vec<EquiJoin>, String> {
+        let existing = vec![
+            table("users", &["id", "age"]),
+            table("visits", &["user_id", "page"]),
+            table("pages", &["page", "title"]),
+        ];
+        let description = SqlAnalyzer::new(query).describe().unwrap();
+        let resolver = Resolver::new(&description, &existing, IdentifierRules::sqlite()).unwrap();
+        equi_joins(&description, &resolver)
+    }
+
+    fn key(table: &str, column: &str) -> JoinKey {
+        JoinKey {
+            table: table.to_string(),
+            column: column.to_string(),
+        }
+    }
+
+    #[test]
+    fn equi_joins_are_found() {
+        assert!(joins("SELECT count(*) FROM users").unwrap().is_empty());
+
+        let found = joins(
+            "SELECT count(*) FROM users u JOIN visits v ON v.user_id = u.id AND v.page > 1 \
+            JOIN pages USING (page)",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                EquiJoin {
+                    left: 0,
+                    left_key: key("users", "id"),
+                    right_key: key("visits", "user_id"),
+                    self_join: false,
+                },
+                EquiJoin {
+                    left: 1,
+                    left_key: key("visits", "page"),
+                    right_key: key("pages", "page"),
+                    self_join: false,
+                },
+            ],
+            found
+        );
+
+        let found = joins("SELECT count(*) FROM users a JOIN users b ON a.age = b.age").unwrap();
+        assert!(found[0].self_join);
+    }
+
+    #[test]
+    fn unbounded_joins_are_rejected() {
+        for query in [
+            "SELECT count(*) FROM users, visits",
+            "SELECT count(*) FROM users CROSS JOIN visits",
+            "SELECT count(*) FROM users JOIN visits ON users.id < visits.user_id",
+            "SELECT count(*) FROM users JOIN visits ON users.id = 1 OR visits.page = 2",
+            "SELECT count(*) FROM users LEFT JOIN visits ON users.id = visits.user_id",
+            "SELECT count(*) FROM users JOIN (SELECT user_id FROM visits) v ON users.id = v.user_id",
+            "SELECT count(*) FROM (SELECT age FROM users JOIN visits ON id = user_id) t",
+        ] {
+            assert!(joins(query).is_err(), "{query}");
+        }
+    }
+}
@@ -1 +1,9 @@
+pub mod aggregations;
+pub mod allocation;
 pub mod analyzer;
+pub mod joins;
+pub mod pipeline;
+pub mod resolver;
+pub mod results;
+pub mod rewriter;
+pub mod validator;
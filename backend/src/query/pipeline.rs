@@ -0,0 +1,1767 @@
+use crate::accountant::{Accountant, Composition, Release};
+use crate::database::audit::{AuditEntry, AuditLog, AuditOutcome};
+use crate::database::database::Database;
+use crate::database::ledger::Ledger;
+use crate::database::schema::{Column, PrivacyBudget, PrivacyUnit, Table};
+use crate::logging::{self, Event};
+use crate::query::aggregations::AggregationPolicy;
+use crate::query::allocation::BudgetAllocation;
+use crate::query::analyzer::{self, Aggregate, Projection, QueryDescription, Relation};
+use crate::query::joins::{self, JoinKey};
+use crate::query::resolver::Resolver;
+use crate::query::results::{ColumnKind, NoisedValue, ResultColumn, ResultSet, ResultValue};
+use crate::query::rewriter::{
+    self, moment_label, GROUP_KEY_PREFIX, KEY_FREQUENCY_LABEL, PARTITION_SIZE_LABEL,
+    QUANTILE_VALUE_LABEL,
+};
+use crate::query::validator;
+use crate::transforms::elastic::{smooth_stability, JoinStep};
+use crate::transforms::mechanisms::{MechanismRegistry, NoiseMechanism, DEFAULT_MECHANISM};
+use crate::transforms::moments::{half_width, midpoint, noised_moment, Moment, Sums};
+use crate::transforms::partition::{keep_partition, validate_partition_parameters};
+use crate::transforms::quantile::{exponential_quantile, quantile_scale, validate_quantile};
+use rand::RngCore;
+use std::collections::HashMap;
+
+/// Everything needed to answer a query, borrowed from the application state.
+pub struct QueryPipeline<'a> {
+    pub database: &'a mut Database,
+    pub tables: &'a mut Vec<Table>,
+    pub mechanisms: &'a MechanismRegistry,
+    /// The aggregates the data owner allows on each column.
+    pub aggregations: &'a AggregationPolicy,
+    /// Where every charge is recorded, see `database::ledger::Ledger`.
+    pub ledger: &'a mut Ledger,
+    /// Composes the charges into what every table has spent, see `accountant::Accountant`.
+    pub accountant: &'a Accountant,
+    /// The analyst the query is run for, every charge is attributed to them.
+    pub analyst: &'a str,
+    /// Where every query is recorded, answered or not, see `database::audit::AuditLog`.
+    pub audit: &'a mut AuditLog,
+    /// The source of all noise, see `transforms::rng::NoiseRng`.
+    pub rng: &'a mut dyn RngCore,
+}
+
+/// An aggregate of the query together with the column it is computed over.
+#[derive(Debug, Clone)]
+pub struct UsedColumn {
+    /// The name the aggregate is returned under.
+    pub label: String,
+    /// The lowercased aggregate function.
+    pub function: String,
+    /// The position of the aggregate in the SELECT list.
+    pub position: usize,
+    pub column: Column,
+    /// The quantile released with the exponential mechanism instead of noising the
+    /// aggregate, 0 for MIN and 1 for MAX.
+    pub quantile: Option<f64>,
+    /// Whether the argument is clamped into the bounds of `column`, whose sensitivity
+    /// then follows from them.
+    pub clamped: bool,
+    /// The moment released from noised sums instead of noising the aggregate.
+    pub moment: Option<Moment>,
+    /// How many rows the aggregate is computed over a single privacy unit may contribute,
+    /// its sensitivity is that many times the sensitivity of a row.
+    pub contributions: f64,
+    /// Whether the aggregate is over joined tables, `contributions` is then twice the
+    /// smooth bound on the stability of the joins, see `transforms::elastic`.
+    pub joined: bool,
+    /// The (epsilon, delta) the aggregate is noised with.
+    pub cost: PrivacyBudget,
+}
+
+impl UsedColumn {
+    /// How many releases answering the aggregate takes, the budget is split evenly
+    /// between them.
+    fn components(&self) -> usize {
+        self.moment.map(|moment| moment.components()).unwrap_or(1)
+    }
+
+    /// Whether the aggregate can only produce integers, such aggregates are answered
+    /// with the integer counterpart of the requested mechanism if it has one.
+    fn is_integer_aggregate(&self) -> bool {
+        // Smooth sensitivity is only calibrated for continuous Laplace noise.
+        if self.joined {
+            return false;
+        }
+        match self.function.as_str() {
+            "count" => true,
+            "sum" => self.column.is_integer(),
+            _ => false,
+        }
+    }
+}
+
+/// Result labels are matched ignoring case and whitespace, the database returns them as typed.
+fn normalize_label(label: &str) -> String {
+    label
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// The values of the `__group_key_` labels of a row, joined, the empty string for the
+/// single group of an ungrouped query.
+fn group_key(values: &HashMap<String, &str>) -> String {
+    (0..)
+        .map_while(|position| values.get(&format!("{GROUP_KEY_PREFIX}{position}")))
+        .copied()
+        .collect::<Vec<&str>>()
+        .join("\u{1f}")
+}
+
+/// Groups the rows of a `rewriter::quantile_values` query by their group key.
+pub fn group_quantile_values(rows: &[HashMap<String, String>]) -> HashMap<String, Vec<f64>> {
+    let mut groups: HashMap<String, Vec<f64>> = HashMap::new();
+    for row in rows.iter() {
+        let values: HashMap<String, &str> = row
+            .iter()
+            .map(|(k, v)| (normalize_label(k), v.as_str()))
+            .collect();
+        let values_of_group = groups.entry(group_key(&values)).or_default();
+        // NULLs are skipped, as the database does for MIN and MAX.
+        if let Some(value) = values
+            .get(QUANTILE_VALUE_LABEL)
+            .and_then(|value| value.parse::<f64>().ok())
+        {
+            values_of_group.push(value);
+        }
+    }
+    groups
+}
+
+/// Applies differential privacy transformations to the query results.
+///
+/// # Parameters
+/// - `columns`: The columns of the result, in the order of the SELECT list.
+/// - `used_columns`: The aggregates of the query and the columns they are computed over.
+/// - `query_result`: A vector of hashmaps representing the query results.
+/// - `quantile_values`: The values of every quantile by group, keyed by its label, see
+///   `group_quantile_values`.
+/// - `mechanism`: The noise mechanism used to perturb the results.
+/// - `mechanisms`: The registry integer counterparts of `mechanism` are looked up in.
+/// - `rng`: The source of randomness for the noise.
+///
+/// # Returns
+/// The result set with a row per result, group keys as they are and aggregates noised.
+/// _Documentation generated by ChatGPT._
+pub fn apply_transforms(
+    columns: Vec<ResultColumn>,
+    used_columns: Vec<UsedColumn>,
+    query_result: Vec<HashMap<String, String>>,
+    quantile_values: &HashMap<String, HashMap<String, Vec<f64>>>,
+    mechanism: &dyn NoiseMechanism,
+    mechanisms: &MechanismRegistry,
+    rng: &mut dyn RngCore,
+) -> ResultSet {
+    let integer_mechanism = mechanism
+        .integer_counterpart()
+        .and_then(|name| mechanisms.get(name).ok());
+
+    let label_to_column: HashMap<String, &UsedColumn> = used_columns
+        .iter()
+        .map(|used_column| (normalize_label(&used_column.label), used_column))
+        .collect();
+
+    // The noise is drawn from a single `rng`, the cells are therefore visited in a plain loop.
+    let mut rows: Vec<Vec<ResultValue>> = vec![];
+    for result in query_result.iter() {
+        let values: HashMap<String, &str> = result
+            .iter()
+            .map(|(k, v)| (normalize_label(k), v.as_str()))
+            .collect();
+        let mut row: Vec<ResultValue> = vec![];
+        for result_column in columns.iter() {
+            let label = normalize_label(&result_column.label);
+            let value = values.get(&label).copied().unwrap_or("Null");
+            if result_column.kind == ColumnKind::GroupKey {
+                row.push(ResultValue::Key(value.to_string()));
+                continue;
+            }
+            // `get_used_columns` rejects the aggregates it cannot noise, a true
+            // aggregate is never released.
+            let Some(&used_column) = label_to_column.get(&label) else {
+                row.push(ResultValue::Key("Null".to_string()));
+                continue;
+            };
+            let column = &used_column.column;
+            let cost = &used_column.cost;
+            let contributions = used_column.contributions;
+            if let (Some(moment), Some(bounds)) = (used_column.moment, &column.bounds) {
+                let components = used_column.components() as f64;
+                let component_cost =
+                    PrivacyBudget::new(cost.epsilon / components, cost.delta / components);
+                let sum = |component: &str| {
+                    values
+                        .get(&moment_label(component, used_column.position))
+                        .and_then(|value| value.parse::<f64>().ok())
+                        .unwrap_or_default()
+                };
+                let sums = Sums {
+                    count: sum("count"),
+                    sum: sum("sum"),
+                    squares: sum("squares"),
+                };
+                row.push(ResultValue::Noised(NoisedValue {
+                    value: noised_moment(
+                        moment,
+                        &sums,
+                        bounds,
+                        contributions,
+                        mechanism,
+                        &component_cost,
+                        rng,
+                    ),
+                    mechanism: mechanism.name().to_string(),
+                    // The noise on the sum, the count gets less.
+                    scale: Some(
+                        mechanism.scale(half_width(bounds) * contributions, &component_cost),
+                    )
+                    .filter(|_| !used_column.joined),
+                    epsilon: cost.epsilon,
+                    delta: cost.delta,
+                }));
+                continue;
+            }
+            if let (Some(quantile), Some(bounds)) = (used_column.quantile, &column.bounds) {
+                let group_values = quantile_values
+                    .get(&label)
+                    .and_then(|groups| groups.get(&group_key(&values)))
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                row.push(ResultValue::Noised(NoisedValue {
+                    // A unit moves the rank of a value by as many rows as it contributes.
+                    value: exponential_quantile(
+                        group_values,
+                        quantile,
+                        bounds,
+                        cost.epsilon / contributions,
+                        rng,
+                    ),
+                    mechanism: "exponential".to_string(),
+                    scale: Some(quantile_scale(cost.epsilon / contributions)),
+                    epsilon: cost.epsilon,
+                    delta: cost.delta,
+                }));
+                continue;
+            }
+            // We need unwrap_or_default to handle Null and we are treating
+            // nulls as 0 (my decision)
+            let true_value = value.parse::<f64>().unwrap_or_default();
+            let used_mechanism = match &integer_mechanism {
+                Some(integer_mechanism) if used_column.is_integer_aggregate() => {
+                    integer_mechanism.as_ref()
+                }
+                _ => mechanism,
+            };
+            row.push(ResultValue::Noised(NoisedValue {
+                value: used_mechanism.sample(
+                    true_value,
+                    column.sensitivity * contributions,
+                    cost,
+                    rng,
+                ),
+                mechanism: used_mechanism.name().to_string(),
+                scale: Some(used_mechanism.scale(column.sensitivity * contributions, cost))
+                    .filter(|_| !used_column.joined),
+                epsilon: cost.epsilon,
+                delta: cost.delta,
+            }));
+        }
+        rows.push(row);
+    }
+    ResultSet { columns, rows }
+}
+
+/// Drops the groups whose noised size does not clear the partition selection threshold.
+///
+/// # Parameters
+/// - `query_result`: The rows of a grouped query, each with its `__partition_size`.
+/// - `cost`: The (epsilon, delta) spent on the selection.
+/// - `rng`: The source of randomness for the noise.
+///
+/// # Returns
+/// The rows of the released groups, without their partition size.
+pub fn select_partitions(
+    query_result: Vec<HashMap<String, String>>,
+    cost: &PrivacyBudget,
+    rng: &mut dyn RngCore,
+) -> Vec<HashMap<String, String>> {
+    let mut selected: Vec<HashMap<String, String>> = vec![];
+    for mut result in query_result {
+        let size_label = result
+            .keys()
+            .find(|k| normalize_label(k) == PARTITION_SIZE_LABEL)
+            .cloned();
+        let size = size_label
+            .and_then(|label| result.remove(&label))
+            .and_then(|size| size.parse::<f64>().ok())
+            .unwrap_or_default();
+        if keep_partition(size, cost.epsilon, cost.delta, rng) {
+            selected.push(result);
+        }
+    }
+    selected
+}
+
+/// The quantile `aggregate` asks for, `None` if it is not answered with the exponential
+/// mechanism.
+///
+/// # Errors
+/// Returns an error if a PERCENTILE is not given a column and a constant between 0 and 1.
+fn requested_quantile(aggregate: &Aggregate) -> Result<Option<f64>, String> {
+    let quantile = match aggregate.function.as_str() {
+        "min" => 0.0,
+        "max" => 1.0,
+        "median" => 0.5,
+        "percentile" => {
+            let quantile = match aggregate.arguments.as_slice() {
+                [_, quantile] if quantile.columns.is_empty() => {
+                    quantile.text.trim().parse::<f64>().ok()
+                }
+                _ => None,
+            };
+            quantile.ok_or("PERCENTILE takes a column and a constant between 0 and 1")?
+        }
+        _ => return Ok(None),
+    };
+    validate_quantile(quantile)?;
+    Ok(Some(quantile))
+}
+
+/// Determines which columns are used in the query.
+///
+/// # Parameters
+/// - `projections`: The projections of the query.
+/// - `resolver`: The resolver binding the query's aliases and column references to the schema.
+/// - `aggregations`: The aggregates allowed on each column.
+///
+/// # Returns
+/// Every aggregate of the query together with the column it is computed over.
+///
+/// # Errors
+/// Returns an error if an aggregate is not allowed, is not computed over exactly one
+/// column, if its column cannot be resolved, if it needs bounds its column does not have
+/// or if its column has no sensitivity, noise of scale zero would release it exactly.
+///
+/// A SUM is marked clamped, the sensitivity of its column is derived from the bounds.
+pub fn get_used_columns(
+    projections: &[Projection],
+    resolver: &Resolver,
+    aggregations: &AggregationPolicy,
+) -> Result<Vec<UsedColumn>, String> {
+    let mut used_columns: Vec<UsedColumn> = vec![];
+    for (position, projection) in projections.iter().enumerate() {
+        let Some(aggregate) = &projection.aggregate else {
+            continue;
+        };
+        let quantile = requested_quantile(aggregate)
+            .map_err(|error| format!("{error}: {}", projection.expression.text))?;
+        // The percentile itself is a constant, only the first argument is over a column.
+        let arguments = match aggregate.arguments.as_slice() {
+            [argument, _] if aggregate.function == "percentile" => std::slice::from_ref(argument),
+            arguments => arguments,
+        };
+        // Sensitivities are per column, the argument may only reference a single one.
+        let column_ref = match arguments {
+            [argument] => match argument.columns.as_slice() {
+                [column_ref] => Some(column_ref),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some(column_ref) = column_ref else {
+            return Err(format!(
+                "Aggregates must be computed over exactly one column: {}",
+                projection.expression.text
+            ));
+        };
+        let mut column = resolver.column(column_ref)?;
+        aggregations
+            .check(&aggregate.function, &column)
+            .map_err(|error| format!("{error}: {}", projection.expression.text))?;
+        let moment = Moment::of(&aggregate.function);
+        let clamped = aggregate.function == "sum";
+        if (clamped || quantile.is_some() || moment.is_some()) && column.bounds.is_none() {
+            return Err(format!(
+                "{} needs bounds on {}.{}, the data owner has not set them: {}",
+                aggregate.function.to_uppercase(),
+                column.table_name,
+                column.name,
+                projection.expression.text
+            ));
+        }
+        if let (true, Some(bounds)) = (clamped, &column.bounds) {
+            column.sensitivity = bounds.sum_sensitivity();
+        }
+        let noised = quantile.is_none() && moment.is_none();
+        if noised && !(column.sensitivity.is_finite() && column.sensitivity > 0.0) {
+            return Err(format!(
+                "{}.{} has no sensitivity, the data owner has not set one: {}",
+                column.table_name, column.name, projection.expression.text
+            ));
+        }
+        column.usage = Some(projection.label().to_string());
+        used_columns.push(UsedColumn {
+            label: projection.label().to_string(),
+            function: aggregate.function.to_owned(),
+            position,
+            column,
+            quantile,
+            clamped,
+            moment,
+            contributions: 1.0,
+            joined: false,
+            cost: PrivacyBudget::default(),
+        });
+    }
+    Ok(used_columns)
+}
+
+/// The privacy unit whose contributions the query has to bound, if it reads a table with one.
+///
+/// # Errors
+/// Returns an error if a table with a privacy unit is read together with other tables,
+/// joined or in a subquery.
+fn privacy_unit(
+    description: &QueryDescription,
+    used_tables: &[Table],
+) -> Result<Option<PrivacyUnit>, String> {
+    let Some(table) = used_tables
+        .iter()
+        .find(|table| table.privacy_unit.is_some())
+    else {
+        return Ok(None);
+    };
+    let alone = used_tables.len() == 1
+        && description.joins.is_empty()
+        && description.subqueries.is_empty()
+        && matches!(description.from.as_slice(), [Relation::Table { .. }]);
+    if !alone {
+        return Err(format!(
+            "{} has a privacy unit, it can only be queried on its own",
+            table.name
+        ));
+    }
+    Ok(table.privacy_unit.clone())
+}
+
+pub fn sanitize_input(input: &str) -> String {
+    input.replace("“", "\"").replace("”", "\"")
+}
+
+impl QueryPipeline<'_> {
+    /// Executes an SQL query with differential privacy applied.
+    ///
+    /// # Parameters
+    /// - `query`: The SQL query to be executed.
+    /// - `budget`: The epsilon spent on the query.
+    /// - `mechanism`: The name of a registered noise mechanism, defaults to Laplace.
+    /// - `delta`: The delta spent on the query, required by (epsilon, delta) mechanisms.
+    /// - `allocation`: How `budget` and `delta` are spread over the aggregates of the query.
+    ///
+    /// Grouped queries additionally spend `budget` and `delta` on partition selection,
+    /// only groups whose noised size clears the selection threshold are released.
+    ///
+    /// The budget is reserved once the query is known to be valid and affordable, and
+    /// charged once it ran. Queries rejected before running cost nothing.
+    ///
+    /// Every query is recorded in the audit log together with its outcome, no result is
+    /// released unless it was recorded.
+    ///
+    /// # Returns
+    /// A result containing either the transformed query results or an error message.
+    pub fn execute(
+        &mut self,
+        query: &str,
+        budget: f64,
+        mechanism: Option<&str>,
+        delta: Option<f64>,
+        allocation: &BudgetAllocation,
+    ) -> Result<ResultSet, String> {
+        let mut entry = AuditEntry::new(&self.database.fingerprint, self.analyst, query);
+        let result = self.answer(query, budget, mechanism, delta, allocation, &mut entry);
+        match &result {
+            Ok(_) => entry.outcome = AuditOutcome::Answered,
+            Err(error) => entry.message = Some(error.to_owned()),
+        }
+        self.audit.record(&entry)?;
+        result
+    }
+
+    /// Answers `query` as `execute` does, filling in `entry` as the query progresses.
+    ///
+    /// `entry` is marked failed as soon as the query is charged, it is rejected until then.
+    fn answer(
+        &mut self,
+        query: &str,
+        budget: f64,
+        mechanism: Option<&str>,
+        delta: Option<f64>,
+        allocation: &BudgetAllocation,
+        entry: &mut AuditEntry,
+    ) -> Result<ResultSet, String> {
+        let mechanism = self
+            .mechanisms
+            .get(mechanism.unwrap_or(DEFAULT_MECHANISM))?;
+        if budget <= 0.0 {
+            return Err("The budget of a query must be positive".to_string());
+        }
+        let sanitized_query = sanitize_input(query);
+        let analyzer = analyzer::SqlAnalyzer::for_backend(
+            &sanitized_query,
+            self.database.flavour,
+            self.database.identifiers,
+        );
+        let description = analyzer.describe()?;
+        validator::validate(&description)?;
+        let resolver = Resolver::new(&description, self.tables, self.database.identifiers)?;
+        let mut used_columns =
+            get_used_columns(&description.projections, &resolver, self.aggregations)?;
+        let used_tables = resolver.tables();
+        entry.tables = used_tables
+            .iter()
+            .map(|table| table.name.to_owned())
+            .collect();
+        let columns: Vec<ResultColumn> = description
+            .projections
+            .iter()
+            .map(|projection| ResultColumn {
+                label: projection.label().to_string(),
+                kind: match &projection.aggregate {
+                    Some(aggregate) => ColumnKind::Aggregate {
+                        function: aggregate.function.to_owned(),
+                    },
+                    None => ColumnKind::GroupKey,
+                },
+            })
+            .collect();
+
+        let mut query = analyzer.query()?;
+        // Every aggregate is private for each privacy unit once the rows of each are capped.
+        let unit = privacy_unit(&description, &used_tables)?;
+        let units = match &unit {
+            Some(unit) => {
+                let units = rewriter::bound_contributions(&mut query, unit, self.database.flavour)?;
+                let contributions = unit.contributions(!description.group_by.is_empty()) as f64;
+                for used_column in used_columns.iter_mut() {
+                    used_column.contributions = contributions;
+                }
+                Some(units)
+            }
+            None => None,
+        };
+        // A joined row is repeated once for every row it matches, the noise is calibrated
+        // to the max frequencies of the join keys once the query has run.
+        let joins = joins::equi_joins(&description, &resolver)?;
+        if !joins.is_empty() {
+            if !description.group_by.is_empty() {
+                return Err(
+                    "Grouped joins are not supported, a row can reach any number of groups"
+                        .to_string(),
+                );
+            }
+            if let Some(used_column) = used_columns.iter().find(|used| used.quantile.is_some()) {
+                return Err(format!(
+                    "{} cannot be answered over joined tables",
+                    used_column.function.to_uppercase()
+                ));
+            }
+            if mechanism.name() != "laplace" {
+                return Err(format!(
+                    "Joins are answered with laplace noise, {} is not supported",
+                    mechanism.name()
+                ));
+            }
+            if delta.unwrap_or_default() <= 0.0 {
+                return Err(
+                    "Joins are answered with smooth sensitivity, which needs a positive delta"
+                        .to_string(),
+                );
+            }
+            for used_column in used_columns.iter_mut() {
+                used_column.joined = true;
+            }
+        }
+        // Sequential composition, the query costs the sum of what its aggregates are noised with.
+        let requested = PrivacyBudget::new(budget, delta.unwrap_or_default());
+        let (aggregates_budget, selection_budget) = if description.group_by.is_empty() {
+            (requested, PrivacyBudget::default())
+        } else {
+            allocation.set_aside_selection(requested, used_columns.len())
+        };
+        let shares = allocation.split(aggregates_budget, used_columns.len())?;
+        let mut releases: Vec<Release> = vec![];
+        for (used_column, share) in used_columns.iter_mut().zip(shares) {
+            // Quantiles are released with the exponential mechanism, which is pure epsilon-DP.
+            if used_column.quantile.is_some() {
+                used_column.cost = PrivacyBudget::new(share.epsilon, 0.0);
+                releases.push(Release::new(used_column.cost, None));
+                continue;
+            }
+            // Every sum of a moment is a release of its own.
+            let components = used_column.components();
+            let component_cost = if used_column.joined {
+                // The smoothing spends delta, which Laplace noise alone does not.
+                PrivacyBudget::new(
+                    share.epsilon / components as f64,
+                    share.delta / components as f64,
+                )
+            } else {
+                mechanism.privacy_cost(
+                    share.epsilon / components as f64,
+                    share.delta / components as f64,
+                )?
+            };
+            used_column.cost = PrivacyBudget::new(
+                component_cost.epsilon * components as f64,
+                component_cost.delta * components as f64,
+            );
+            for _ in 0..components {
+                releases.push(Release::new(
+                    component_cost,
+                    mechanism.zcdp_rho(&component_cost),
+                ));
+            }
+        }
+        let selection_cost = if description.group_by.is_empty() {
+            None
+        } else {
+            // A unit is counted once in each of the groups it contributes to.
+            let groups = unit
+                .as_ref()
+                .map(|unit| unit.max_groups as f64)
+                .unwrap_or(1.0);
+            let selection_cost = PrivacyBudget::new(
+                selection_budget.epsilon / groups,
+                selection_budget.delta / groups,
+            );
+            validate_partition_parameters(selection_cost.epsilon, selection_cost.delta)?;
+            rewriter::add_partition_size(&mut query, units.as_deref())?;
+            releases.push(Release::new(selection_budget, None));
+            Some(selection_cost)
+        };
+        // The database never computes a quantile, their values are read by queries of
+        // their own and the quantiles released from them.
+        let mut values_queries: Vec<(String, String)> = vec![];
+        for used_column in used_columns.iter().filter(|used| used.quantile.is_some()) {
+            let values = rewriter::quantile_values(&query, used_column.position)?;
+            values_queries.push((normalize_label(&used_column.label), values.to_string()));
+            rewriter::mask_aggregate(&mut query, used_column.position, &used_column.label)?;
+        }
+        if !values_queries.is_empty() && !description.group_by.is_empty() {
+            rewriter::add_group_keys(&mut query)?;
+        }
+        for used_column in used_columns.iter() {
+            if let (Some(moment), Some(bounds)) = (used_column.moment, &used_column.column.bounds) {
+                rewriter::add_moments(
+                    &mut query,
+                    used_column.position,
+                    &used_column.label,
+                    bounds,
+                    midpoint(bounds),
+                    moment != Moment::Mean,
+                    self.database.flavour,
+                )?;
+            }
+        }
+        // A single row moves a clamped aggregate by no more than the sensitivity derived
+        // from the bounds, whatever the data holds.
+        for used_column in used_columns.iter().filter(|used| used.clamped) {
+            if let Some(bounds) = &used_column.column.bounds {
+                rewriter::clamp_argument(
+                    &mut query,
+                    used_column.position,
+                    bounds,
+                    self.database.flavour,
+                )?;
+            }
+        }
+        let integer_mechanism = mechanism
+            .integer_counterpart()
+            .filter(|name| self.mechanisms.get(name).is_ok());
+        for used_column in used_columns.iter() {
+            let name = match integer_mechanism {
+                _ if used_column.quantile.is_some() => "exponential",
+                Some(name) if used_column.is_integer_aggregate() => name,
+                _ => mechanism.name(),
+            };
+            if !entry.mechanisms.iter().any(|used| used == name) {
+                entry.mechanisms.push(name.to_string());
+            }
+        }
+        if selection_cost.is_some() {
+            entry.mechanisms.push("partition_selection".to_string());
+        }
+
+        // Nothing is spent on a query the tables cannot afford.
+        for table in used_tables.iter() {
+            if !self.accountant.admits(
+                self.ledger,
+                &self.database.fingerprint,
+                self.analyst,
+                &table.name,
+                &releases,
+            )? {
+                let left = self.accountant.analyst_remaining(
+                    self.ledger,
+                    &self.database.fingerprint,
+                    self.analyst,
+                    &table.name,
+                )?;
+                return Err(format!(
+                    "Insufficient budget for {} on {}: {} left, the query costs {}",
+                    self.analyst,
+                    table.name,
+                    left,
+                    self.accountant.composition.compose(&releases, &left)
+                ));
+            }
+        }
+
+        // Preparing reads no data, a query that fails here costs nothing.
+        let sql = query.to_string();
+        self.database.prepare_query(&sql)?;
+        for (_, values_sql) in values_queries.iter() {
+            self.database.prepare_query(values_sql)?;
+        }
+        let mut join_keys: Vec<&JoinKey> = vec![];
+        for join in joins.iter() {
+            for key in [&join.left_key, &join.right_key] {
+                if !join_keys.contains(&key) {
+                    join_keys.push(key);
+                }
+            }
+        }
+        let frequency_queries: Vec<(&JoinKey, String)> = join_keys
+            .into_iter()
+            .map(|key| (key, rewriter::key_frequency(&key.table, &key.column)))
+            .collect();
+        for (_, frequency_sql) in frequency_queries.iter() {
+            self.database.prepare_query(frequency_sql)?;
+        }
+        let reservations = self.reserve(&used_tables, &releases)?;
+        let charged = Composition::Basic.compose(&releases, &PrivacyBudget::default());
+        (entry.epsilon, entry.delta) = (charged.epsilon, charged.delta);
+        entry.outcome = AuditOutcome::Failed;
+        // Once the query runs its errors may depend on the data, e.g. an overflowing SUM,
+        // the reservation is therefore kept whatever the outcome.
+        let query_result = self.database.execute_query(&sql).and_then(|query_result| {
+            let mut quantile_values: HashMap<String, HashMap<String, Vec<f64>>> = HashMap::new();
+            for (label, values_sql) in values_queries.iter() {
+                let rows = self.database.execute_query(values_sql)?;
+                quantile_values.insert(label.to_owned(), group_quantile_values(&rows));
+            }
+            let mut frequencies: HashMap<JoinKey, f64> = HashMap::new();
+            for (key, frequency_sql) in frequency_queries.iter() {
+                let rows = self.database.execute_query(frequency_sql)?;
+                let frequency = rows
+                    .first()
+                    .and_then(|row| row.get(KEY_FREQUENCY_LABEL))
+                    .and_then(|frequency| frequency.parse::<f64>().ok())
+                    .unwrap_or_default();
+                frequencies.insert((*key).clone(), frequency);
+            }
+            Ok((query_result, quantile_values, frequencies))
+        });
+        for reservation in reservations {
+            self.ledger.commit(reservation)?;
+        }
+        let (mut query_result, quantile_values, frequencies) = query_result?;
+        let steps: Vec<JoinStep> = joins.iter().map(|join| join.step(&frequencies)).collect();
+        for used_column in used_columns.iter_mut().filter(|used| used.joined) {
+            let components = used_column.components() as f64;
+            let smooth = smooth_stability(
+                &steps,
+                used_column.cost.epsilon / components,
+                used_column.cost.delta / components,
+            );
+            // Laplace noise of twice the smooth bound is (epsilon, delta)-DP.
+            used_column.contributions = 2.0 * smooth;
+        }
+        if let Some(selection_cost) = &selection_cost {
+            query_result = select_partitions(query_result, selection_cost, self.rng);
+        }
+        Ok(apply_transforms(
+            columns,
+            used_columns,
+            query_result,
+            &quantile_values,
+            mechanism.as_ref(),
+            self.mechanisms,
+            self.rng,
+        ))
+    }
+
+    /// Reserves `releases` on every table in `used_tables`, in the ledger and in memory.
+    ///
+    /// # Returns
+    /// The ledger reservations, nothing is reserved if any of them fails.
+    fn reserve(&mut self, used_tables: &[Table], releases: &[Release]) -> Result<Vec<i64>, String> {
+        let mut reservations: Vec<i64> = vec![];
+        for table in used_tables.iter() {
+            for release in releases.iter() {
+                match self.ledger.reserve(
+                    &self.database.fingerprint,
+                    &table.name,
+                    self.analyst,
+                    release,
+                ) {
+                    Ok(reservation) => reservations.push(reservation),
+                    Err(error) => {
+                        for reservation in reservations {
+                            self.ledger.release(reservation)?;
+                        }
+                        return Err(error);
+                    }
+                }
+            }
+        }
+        for table in self.tables.iter_mut() {
+            if !used_tables
+                .iter()
+                .any(|used_table| used_table.name == table.name)
+            {
+                continue;
+            }
+            let remaining =
+                self.accountant
+                    .remaining(self.ledger, &self.database.fingerprint, &table.name)?;
+            logging::log(Event::BudgetCharged {
+                analyst: self.analyst.to_string(),
+                table: table.name.to_owned(),
+                remaining,
+            });
+            if remaining.epsilon <= 0.0 {
+                logging::log(Event::BudgetExhausted {
+                    table: table.name.to_owned(),
+                });
+            }
+            table.privacy_budget = remaining;
+        }
+        Ok(reservations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueryPipeline;
+    use crate::accountant::{Accountant, Composition};
+    use crate::database::audit::{AuditFilter, AuditLog, AuditOutcome, ExportFormat};
+    use crate::database::database::{ConnectionTypes, Database, SupportedDatabases};
+    use crate::database::identifiers::IdentifierRules;
+    use crate::database::ledger::Ledger;
+    use crate::database::schema::{ColumnBounds, PrivacyBudget, PrivacyUnit, Schema, Table};
+    use crate::query::aggregations::{AggregationPolicy, SUPPORTED_AGGREGATIONS};
+    use crate::query::allocation::BudgetAllocation;
+    use crate::query::results::{ColumnKind, ResultSet, ResultValue};
+    use crate::transforms::laplace_scale;
+    use crate::transforms::mechanisms::MechanismRegistry;
+    use crate::transforms::rng::NoiseRng;
+    use rusqlite::Connection as SqliteConnection;
+
+    const FINGERPRINT: &str = "sqlite::memory:";
+    const ANALYST: &str = "alice";
+
+    /// Sums over age and salary are clamped into [0, 100] and [0, 5000].
+    fn users_database() -> (Database, Vec<Table>, Ledger) {
+        let (database, mut tables, ledger) = test_database(
+            "CREATE TABLE users (age Integer, salary Real);
+            INSERT INTO users VALUES (21, 1000.5), (34, 2000.25), (55, 3000.0);",
+        );
+        tables[0].columns[0].bounds = Some(ColumnBounds::new(0.0, 100.0).unwrap());
+        tables[0].columns[1].bounds = Some(ColumnBounds::new(0.0, 5000.0).unwrap());
+        (database, tables, ledger)
+    }
+
+    /// Allows every supported aggregate, not only the default ones.
+    fn all_aggregations() -> AggregationPolicy {
+        let functions = SUPPORTED_AGGREGATIONS.map(String::from);
+        AggregationPolicy::new(&functions, IdentifierRules::sqlite()).unwrap()
+    }
+
+    /// A database created by `setup`, every table and alice get a budget of 10.
+    fn test_database(setup: &str) -> (Database, Vec<Table>, Ledger) {
+        let connection = SqliteConnection::open_in_memory().unwrap();
+        connection.execute_batch(setup).unwrap();
+        let mut database = Database {
+            flavour: SupportedDatabases::SQLite,
+            connection: ConnectionTypes::SQLite(connection),
+            identifiers: IdentifierRules::sqlite(),
+            fingerprint: FINGERPRINT.to_string(),
+        };
+        let mut ledger = Ledger::in_memory().unwrap();
+        let mut tables = Schema::from_connection(&mut database);
+        for table in tables.iter_mut() {
+            let budget = PrivacyBudget::new(10.0, 0.0);
+            ledger
+                .set_allowance(FINGERPRINT, &table.name, &budget)
+                .unwrap();
+            ledger
+                .set_analyst_allowance(FINGERPRINT, ANALYST, &table.name, &budget)
+                .unwrap();
+            for column in table.columns.iter_mut() {
+                column.sensitivity = 1.0;
+            }
+        }
+        Accountant::default()
+            .load_budgets(&ledger, FINGERPRINT, &mut tables)
+            .unwrap();
+        (database, tables, ledger)
+    }
+
+    fn run_seeded(seed: u64, query: &str) -> Vec<f64> {
+        let (mut database, mut tables, mut ledger) = users_database();
+        let mut rng = NoiseRng::seeded(seed).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        pipeline
+            .execute(query, 1.0, None, None, &BudgetAllocation::Even)
+            .unwrap()
+            .rows
+            .iter()
+            .flatten()
+            .filter_map(|value| match value {
+                ResultValue::Noised(value) => Some(value.value),
+                ResultValue::Key(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seeded_noise_is_reproducible() {
+        let query = "SELECT sum(salary) FROM users;";
+        assert_eq!(run_seeded(7, query), run_seeded(7, query));
+        assert_ne!(run_seeded(7, query), run_seeded(8, query));
+    }
+
+    #[test]
+    fn budget_is_deducted() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        pipeline
+            .execute(
+                "SELECT count(age) FROM users;",
+                2.5,
+                None,
+                None,
+                &BudgetAllocation::Even,
+            )
+            .unwrap();
+        assert_eq!(PrivacyBudget::new(7.5, 0.0), tables[0].privacy_budget);
+        assert_eq!(
+            PrivacyBudget::new(2.5, 0.0),
+            Accountant::default()
+                .spent(&ledger, FINGERPRINT, "users")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn identifiers_ignore_case() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        let results = pipeline
+            .execute(
+                "SELECT SUM(Age) FROM Users;",
+                1.0,
+                None,
+                None,
+                &BudgetAllocation::Even,
+            )
+            .unwrap();
+        assert_eq!(Some(0), results.column_index("SUM(Age)"));
+        assert!(matches!(results.rows[0][0], ResultValue::Noised(_)));
+    }
+
+    #[test]
+    fn result_set() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        let results = pipeline
+            .execute(
+                "SELECT count(age) AS people, sum(salary) FROM users;",
+                2.0,
+                None,
+                None,
+                &BudgetAllocation::Even,
+            )
+            .unwrap();
+        let labels: Vec<&str> = results
+            .columns
+            .iter()
+            .map(|column| column.label.as_str())
+            .collect();
+        assert_eq!(["people", "sum(salary)"].to_vec(), labels);
+        assert_eq!(
+            ColumnKind::Aggregate {
+                function: "count".to_string()
+            },
+            results.columns[0].kind
+        );
+        assert_eq!(1, results.rows.len());
+        let noised: Vec<(&str, Option<f64>, f64)> = results.rows[0]
+            .iter()
+            .filter_map(|value| match value {
+                ResultValue::Noised(value) => {
+                    Some((value.mechanism.as_str(), value.scale, value.epsilon))
+                }
+                ResultValue::Key(_) => None,
+            })
+            .collect();
+        assert_eq!(2, noised.len());
+        // The budget is split evenly over both aggregates.
+        assert_eq!(("geometric", Some(1.0), 1.0), noised[0]);
+        assert_eq!("laplace", noised[1].0);
+        assert!(noised[1].1.is_some_and(|scale| scale >= 1.0));
+    }
+
+    #[test]
+    fn row_level_data_is_rejected() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        assert!(pipeline
+            .execute(
+                "SELECT age FROM users;",
+                1.0,
+                None,
+                None,
+                &BudgetAllocation::Even
+            )
+            .is_err());
+        assert_eq!(PrivacyBudget::new(10.0, 0.0), tables[0].privacy_budget);
+    }
+
+    #[test]
+    fn small_groups_are_suppressed() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        if let ConnectionTypes::SQLite(connection) = &database.connection {
+            connection
+                .execute_batch(
+                    "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 100)
+                    INSERT INTO users SELECT 21, 1000.0 FROM n;",
+                )
+                .unwrap();
+        }
+        let budget = PrivacyBudget::new(10.0, 1e-5);
+        ledger.set_allowance(FINGERPRINT, "users", &budget).unwrap();
+        ledger
+            .set_analyst_allowance(FINGERPRINT, ANALYST, "users", &budget)
+            .unwrap();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        let results = pipeline
+            .execute(
+                "SELECT age, count(salary) FROM users GROUP BY age;",
+                1.0,
+                None,
+                Some(1e-6),
+                &BudgetAllocation::Even,
+            )
+            .unwrap();
+        assert_eq!(1, results.rows.len());
+        assert_eq!(ResultValue::Key("21".to_string()), results.rows[0][0]);
+        assert_eq!(Some(1), results.column_index("count(salary)"));
+        // Partition selection takes half of the request, the geometric count spends no delta.
+        assert_eq!(9.0, tables[0].privacy_budget.epsilon);
+        assert!((tables[0].privacy_budget.delta - 9.5e-6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn groups_are_not_picked_on_exact_aggregates() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        for query in [
+            "SELECT age, count(salary) FROM users GROUP BY age ORDER BY count(salary) DESC LIMIT 1;",
+            "SELECT age, count(salary) FROM users GROUP BY age ORDER BY count(salary);",
+            "SELECT age, count(salary) FROM users GROUP BY age ORDER BY age LIMIT 1 OFFSET 1;",
+        ] {
+            assert!(
+                pipeline
+                    .execute(query, 1.0, None, Some(1e-6), &BudgetAllocation::Even)
+                    .is_err(),
+                "{query}"
+            );
+        }
+        assert_eq!(10.0, tables[0].privacy_budget.epsilon);
+    }
+
+    #[test]
+    fn aggregates_compose() {
+        let query = "SELECT count(age), sum(age), avg(salary) FROM users;";
+        let (mut database, mut tables, mut ledger) = users_database();
+        tables[0].columns[1].bounds = Some(ColumnBounds::new(0.0, 5000.0).unwrap());
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        pipeline
+            .execute(query, 3.0, None, None, &BudgetAllocation::Even)
+            .unwrap();
+        pipeline
+            .execute(query, 1.0, None, None, &BudgetAllocation::PerAggregate)
+            .unwrap();
+        let weights = BudgetAllocation::Weighted {
+            weights: vec![1.0, 1.0],
+        };
+        assert!(pipeline.execute(query, 1.0, None, None, &weights).is_err());
+        assert_eq!(PrivacyBudget::new(4.0, 0.0), tables[0].privacy_budget);
+    }
+
+    #[test]
+    fn sums_are_clamped_into_bounds() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        if let ConnectionTypes::SQLite(connection) = &database.connection {
+            connection
+                .execute("INSERT INTO users VALUES (40, 1e9);", [])
+                .unwrap();
+        }
+        tables[0].columns[1].bounds = Some(ColumnBounds::new(0.0, 5000.0).unwrap());
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        let result = pipeline
+            .execute(
+                "SELECT sum(salary) FROM users;",
+                5.0,
+                None,
+                None,
+                &BudgetAllocation::Even,
+            )
+            .unwrap();
+        let ResultValue::Noised(sum) = &result.rows[0][0] else {
+            panic!("the sum is not noised");
+        };
+        // The outlier counts as 5000, the sensitivity is the upper bound.
+        assert_eq!(Some(laplace_scale(5000.0, 5.0)), sum.scale);
+        assert!((sum.value - 11000.75).abs() < 20000.0, "{}", sum.value);
+    }
+
+    #[test]
+    fn moments_are_released_from_noised_sums() {
+        let (mut database, mut tables, mut ledger) = test_database(
+            "CREATE TABLE people (age Integer, height Integer);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1000)
+            INSERT INTO people SELECT 40 + 20 * (i % 2), 170 FROM n;",
+        );
+        tables[0].columns[0].bounds = Some(ColumnBounds::new(0.0, 100.0).unwrap());
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &all_aggregations(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        let even = BudgetAllocation::Even;
+        let result = pipeline
+            .execute(
+                "SELECT avg(age), VARIANCE(age), stddev(age) FROM people;",
+                9.0,
+                None,
+                None,
+                &even,
+            )
+            .unwrap();
+        for (value, truth) in result.rows[0].iter().zip([50.0, 100.0, 10.0]) {
+            let ResultValue::Noised(value) = value else {
+                panic!("{value:?} is not noised");
+            };
+            assert_eq!(3.0, value.epsilon);
+            assert!(
+                (value.value - truth).abs() < 5.0,
+                "{truth}: {}",
+                value.value
+            );
+        }
+        // AVG needs bounds, the data owner set none on height.
+        assert!(pipeline
+            .execute("SELECT avg(height) FROM people;", 1.0, None, None, &even)
+            .is_err());
+        assert_eq!(PrivacyBudget::new(1.0, 0.0), tables[0].privacy_budget);
+    }
+
+    #[test]
+    fn contributions_are_bounded_per_unit() {
+        let (mut database, mut tables, mut ledger) = test_database(
+            "CREATE TABLE visits (user_id Integer, city Text, age Integer);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 300)
+            INSERT INTO visits SELECT i, 'paris', 30 FROM n UNION ALL SELECT i, 'paris', 30 FROM n
+            UNION ALL SELECT 0, 'rome', 30 FROM n;",
+        );
+        tables[0].privacy_unit = Some(PrivacyUnit::new("user_id"));
+        let budget = PrivacyBudget::new(10.0, 1e-4);
+        ledger
+            .set_allowance(FINGERPRINT, "visits", &budget)
+            .unwrap();
+        ledger
+            .set_analyst_allowance(FINGERPRINT, ANALYST, "visits", &budget)
+            .unwrap();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        let even = BudgetAllocation::Even;
+        let noised = |result: ResultSet| -> Vec<(String, f64)> {
+            result
+                .rows
+                .iter()
+                .map(|row| match (&row[0], &row[row.len() - 1]) {
+                    (ResultValue::Key(key), ResultValue::Noised(value)) => {
+                        (key.to_owned(), value.value)
+                    }
+                    (_, ResultValue::Noised(value)) => (String::new(), value.value),
+                    _ => panic!("{row:?} is not noised"),
+                })
+                .collect()
+        };
+        // Every user counts once, whatever the number of their visits.
+        let count = noised(
+            pipeline
+                .execute("SELECT count(age) FROM visits;", 1.0, None, None, &even)
+                .unwrap(),
+        );
+        assert!((count[0].1 - 301.0).abs() < 20.0, "{count:?}");
+        // Rome has a single visitor, however often they came.
+        let cities = noised(
+            pipeline
+                .execute(
+                    "SELECT city, count(age) FROM visits GROUP BY city;",
+                    1.0,
+                    None,
+                    Some(1e-5),
+                    &even,
+                )
+                .unwrap(),
+        );
+        assert_eq!(1, cities.len());
+        assert_eq!("paris", cities[0].0);
+        assert!((cities[0].1 - 300.0).abs() < 20.0, "{cities:?}");
+        assert!(pipeline
+            .execute(
+                "SELECT count(v.age) FROM (SELECT age FROM visits) AS v;",
+                1.0,
+                None,
+                None,
+                &even,
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn failed_queries_cost_nothing() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        tables[0].columns[0].bounds = None;
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &all_aggregations(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        let even = BudgetAllocation::Even;
+        for query in [
+            "SELECT sum(salary) FROM users WHERE unknown = 1;",
+            "SELECT sum(salary) FRM users;",
+            // Sums and quantiles need bounds, the data owner set none on age.
+            "SELECT sum(age) FROM users;",
+            "SELECT max(age) FROM users;",
+        ] {
+            assert!(pipeline.execute(query, 1.0, None, None, &even).is_err());
+        }
+        assert!(pipeline
+            .execute("SELECT sum(salary) FROM users;", 10.5, None, None, &even)
+            .is_err());
+        assert_eq!(PrivacyBudget::new(10.0, 0.0), tables[0].privacy_budget);
+        assert_eq!(
+            PrivacyBudget::default(),
+            Accountant::default()
+                .spent(&ledger, FINGERPRINT, "users")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn subqueries_are_refused() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        // One row moves these counts between 0 and the number of rows.
+        for query in [
+            "SELECT count(age) FROM users WHERE EXISTS (SELECT 1 FROM users WHERE salary = 3000.0);",
+            "SELECT count(age) FROM users WHERE age > (SELECT avg(age) FROM users);",
+            "WITH old AS (SELECT age FROM users WHERE age > 50) SELECT count(age) FROM users;",
+            "SELECT count(t.age) FROM (SELECT age FROM users) t;",
+        ] {
+            assert!(
+                pipeline
+                    .execute(query, 1.0, None, None, &BudgetAllocation::Even)
+                    .is_err(),
+                "{query}"
+            );
+        }
+        assert_eq!(PrivacyBudget::new(10.0, 0.0), tables[0].privacy_budget);
+        assert!(ledger.releases(FINGERPRINT, "users").unwrap().is_empty());
+    }
+
+    #[test]
+    fn columns_without_sensitivity_are_refused() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        tables[0].columns[0].sensitivity = 0.0;
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        for mechanism in ["laplace", "geometric"] {
+            let error = pipeline
+                .execute(
+                    "SELECT count(age) FROM users;",
+                    1.0,
+                    Some(mechanism),
+                    None,
+                    &BudgetAllocation::Even,
+                )
+                .unwrap_err();
+            assert!(error.contains("has no sensitivity"), "{error}");
+        }
+        assert_eq!(PrivacyBudget::new(10.0, 0.0), tables[0].privacy_budget);
+    }
+
+    #[test]
+    fn quantiles_use_the_exponential_mechanism() {
+        let (mut database, mut tables, mut ledger) = test_database(
+            "CREATE TABLE visits (city Text, age Integer);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 500)
+            INSERT INTO visits SELECT 'paris', i % 100 FROM n UNION ALL SELECT 'rome', 900 + i % 100 FROM n;",
+        );
+        tables[0].columns[1].bounds = Some(ColumnBounds::new(0.0, 1000.0).unwrap());
+        let budget = PrivacyBudget::new(10.0, 1e-4);
+        ledger
+            .set_allowance(FINGERPRINT, "visits", &budget)
+            .unwrap();
+        ledger
+            .set_analyst_allowance(FINGERPRINT, ANALYST, "visits", &budget)
+            .unwrap();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &all_aggregations(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        let even = BudgetAllocation::Even;
+        let result = pipeline
+            .execute(
+                "SELECT city, MEDIAN(age), percentile(age, 0.9) FROM visits GROUP BY city ORDER BY city;",
+                6.0,
+                None,
+                Some(1e-5),
+                &even,
+            )
+            .unwrap();
+        assert_eq!(2, result.rows.len());
+        for (row, (median, high)) in result.rows.iter().zip([(50.0, 90.0), (950.0, 990.0)]) {
+            for (value, truth) in row[1..].iter().zip([median, high]) {
+                let ResultValue::Noised(value) = value else {
+                    panic!("{value:?} is not noised");
+                };
+                assert_eq!("exponential", value.mechanism);
+                // A third of the request goes to partition selection.
+                assert!((value.epsilon - 2.0).abs() < 1e-12, "{}", value.epsilon);
+                assert_eq!(0.0, value.delta);
+                assert!(
+                    (value.value - truth).abs() < 15.0,
+                    "{truth}: {}",
+                    value.value
+                );
+            }
+        }
+        for query in [
+            "SELECT percentile(age, 90) FROM visits;",
+            "SELECT percentile(age) FROM visits;",
+            "SELECT max(city) FROM visits;",
+        ] {
+            assert!(
+                pipeline.execute(query, 1.0, None, None, &even).is_err(),
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn joins_use_smooth_elastic_sensitivity() {
+        let (mut database, mut tables, mut ledger) = test_database(
+            "CREATE TABLE users (id Integer, age Integer);
+            CREATE TABLE visits (user_id Integer, page Integer);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 100)
+            INSERT INTO users SELECT i, 20 + i % 50 FROM n;
+            INSERT INTO visits SELECT id, 1 FROM users;
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 49)
+            INSERT INTO visits SELECT 1, i FROM n;",
+        );
+        for table in tables.iter_mut().filter(|table| table.name == "users") {
+            table.columns[1].bounds = Some(ColumnBounds::new(0.0, 100.0).unwrap());
+        }
+        let budget = PrivacyBudget::new(10.0, 1e-4);
+        for table in ["users", "visits"] {
+            ledger.set_allowance(FINGERPRINT, table, &budget).unwrap();
+            ledger
+                .set_analyst_allowance(FINGERPRINT, ANALYST, table, &budget)
+                .unwrap();
+        }
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &all_aggregations(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        let even = BudgetAllocation::Even;
+        let result = pipeline
+            .execute(
+                "SELECT count(v.page) FROM users u JOIN visits v ON u.id = v.user_id;",
+                5.0,
+                None,
+                Some(1e-5),
+                &even,
+            )
+            .unwrap();
+        let ResultValue::Noised(count) = &result.rows[0][0] else {
+            panic!("the count is not noised");
+        };
+        // User 1 has 50 visits, the noise is continuous and its scale is not released.
+        assert_eq!(("laplace", None), (count.mechanism.as_str(), count.scale));
+        assert_eq!(
+            PrivacyBudget::new(5.0, 1e-5),
+            PrivacyBudget::new(count.epsilon, count.delta)
+        );
+        assert!((count.value - 149.0).abs() < 400.0, "{}", count.value);
+
+        for (query, mechanism, delta) in [
+            ("SELECT count(page) FROM users, visits;", None, Some(1e-5)),
+            (
+                "SELECT count(page) FROM users JOIN visits ON users.id > visits.user_id;",
+                None,
+                Some(1e-5),
+            ),
+            (
+                "SELECT v.page, count(v.page) FROM users u JOIN visits v ON u.id = v.user_id GROUP BY v.page;",
+                None,
+                Some(1e-5),
+            ),
+            (
+                "SELECT max(u.age) FROM users u JOIN visits v ON u.id = v.user_id;",
+                None,
+                Some(1e-5),
+            ),
+            (
+                "SELECT count(v.page) FROM users u JOIN visits v ON u.id = v.user_id;",
+                None,
+                None,
+            ),
+            (
+                "SELECT count(v.page) FROM users u JOIN visits v ON u.id = v.user_id;",
+                Some("gaussian"),
+                Some(1e-5),
+            ),
+        ] {
+            assert!(
+                pipeline
+                    .execute(query, 1.0, mechanism, delta, &even)
+                    .is_err(),
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn budget_is_never_overdrawn() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        let even = BudgetAllocation::Even;
+        let query = "SELECT sum(age) FROM users;";
+        for _ in 0..10 {
+            pipeline.execute(query, 1.0, None, None, &even).unwrap();
+        }
+        assert!(pipeline.execute(query, 0.1, None, None, &even).is_err());
+        assert_eq!(PrivacyBudget::new(0.0, 0.0), tables[0].privacy_budget);
+    }
+
+    #[test]
+    fn gaussian_queries_compose_in_zcdp() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        let budget = PrivacyBudget::new(3.0, 1e-5);
+        ledger.set_allowance(FINGERPRINT, "users", &budget).unwrap();
+        ledger
+            .set_analyst_allowance(FINGERPRINT, ANALYST, "users", &budget)
+            .unwrap();
+        let accountant = Accountant::new(Composition::Zcdp { delta: 1e-6 });
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &accountant,
+            analyst: ANALYST,
+            audit: &mut AuditLog::in_memory().unwrap(),
+            rng: &mut rng,
+        };
+        // Basic composition would need an epsilon of 5.
+        for _ in 0..10 {
+            pipeline
+                .execute(
+                    "SELECT sum(salary) FROM users;",
+                    0.5,
+                    Some("gaussian"),
+                    Some(1e-7),
+                    &BudgetAllocation::Even,
+                )
+                .unwrap();
+        }
+        let spent = accountant.spent(&ledger, FINGERPRINT, "users").unwrap();
+        assert!(spent.epsilon < 2.0);
+        assert_eq!(1e-6, spent.delta);
+        assert_eq!(3.0 - spent.epsilon, tables[0].privacy_budget.epsilon);
+    }
+
+    #[test]
+    fn analysts_share_the_table_budget() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        ledger
+            .set_analyst_allowance(FINGERPRINT, "bob", "users", &PrivacyBudget::new(2.0, 0.0))
+            .unwrap();
+        let accountant = Accountant::default();
+        let mechanisms = MechanismRegistry::default();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut audit = AuditLog::in_memory().unwrap();
+        let even = BudgetAllocation::Even;
+        let query = "SELECT sum(age) FROM users;";
+        for (analyst, budget, admitted) in [
+            ("bob", 2.0, true),
+            ("bob", 0.5, false),
+            // Carol has no allowance of her own and cannot spend anything.
+            ("carol", 0.5, false),
+            (ANALYST, 8.5, false),
+            (ANALYST, 8.0, true),
+            // Alice has budget left but the table does not.
+            (ANALYST, 0.5, false),
+        ] {
+            let mut pipeline = QueryPipeline {
+                database: &mut database,
+                tables: &mut tables,
+                mechanisms: &mechanisms,
+                aggregations: &AggregationPolicy::default(),
+                ledger: &mut ledger,
+                accountant: &accountant,
+                analyst,
+                audit: &mut audit,
+                rng: &mut rng,
+            };
+            let result = pipeline.execute(query, budget, None, None, &even);
+            assert_eq!(admitted, result.is_ok(), "{analyst} spending {budget}");
+        }
+        assert_eq!(
+            PrivacyBudget::new(2.0, 0.0),
+            accountant
+                .analyst_spent(&ledger, FINGERPRINT, "bob", "users")
+                .unwrap()
+        );
+        assert_eq!(PrivacyBudget::new(0.0, 0.0), tables[0].privacy_budget);
+    }
+
+    #[test]
+    fn queries_are_audited() {
+        let (mut database, mut tables, mut ledger) = users_database();
+        let mut audit = AuditLog::in_memory().unwrap();
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let mut pipeline = QueryPipeline {
+            database: &mut database,
+            tables: &mut tables,
+            mechanisms: &MechanismRegistry::default(),
+            aggregations: &AggregationPolicy::default(),
+            ledger: &mut ledger,
+            accountant: &Accountant::default(),
+            analyst: ANALYST,
+            audit: &mut audit,
+            rng: &mut rng,
+        };
+        let even = BudgetAllocation::Even;
+        let answered = "SELECT count(age), sum(salary) FROM users;";
+        pipeline.execute(answered, 2.0, None, None, &even).unwrap();
+        assert!(pipeline
+            .execute("SELECT age FROM users;", 1.0, None, None, &even)
+            .is_err());
+
+        let entries = audit.entries(&AuditFilter::default()).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(answered, entries[0].query);
+        assert_eq!(ANALYST, entries[0].analyst);
+        assert_eq!(["users".to_string()].to_vec(), entries[0].tables);
+        assert_eq!(
+            ["geometric".to_string(), "laplace".to_string()].to_vec(),
+            entries[0].mechanisms
+        );
+        assert_eq!((2.0, 0.0), (entries[0].epsilon, entries[0].delta));
+        assert_eq!(AuditOutcome::Answered, entries[0].outcome);
+        assert_eq!(AuditOutcome::Rejected, entries[1].outcome);
+        assert_eq!(0.0, entries[1].epsilon);
+        assert!(entries[1].message.is_some());
+        // True values never reach the log.
+        let export = audit
+            .export(&AuditFilter::default(), ExportFormat::Csv)
+            .unwrap();
+        for value in ["1000.5", "2000.25", "6000.75"] {
+            assert!(!export.contains(value));
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use crate::database::identifiers::IdentifierRules;
+use crate::database::schema::{Column, Table};
+use crate::query::analyzer::{ColumnRef, QueryDescription, Relation};
+use std::ops::Range;
+
+/// A relation a query can reference columns of, under its alias or name.
+enum ScopeEntry<'a> {
+    Table {
+        reference: String,
+        table: &'a Table,
+    },
+    /// A derived table, its output columns that are plain column references resolve to the
+    /// underlying schema column, anything else is computed and resolves to `None`.
+    Subquery {
+        reference: Option<String>,
+        columns: Vec<(String, Option<Column>)>,
+    },
+}
+
+impl ScopeEntry<'_> {
+    fn is_referenced_by(&self, qualifier: &str, identifiers: &IdentifierRules) -> bool {
+        match self {
+            ScopeEntry::Table { reference, .. } => identifiers.same_table(reference, qualifier),
+            ScopeEntry::Subquery { reference, .. } => reference
+                .as_deref()
+                .is_some_and(|reference| identifiers.same_table(reference, qualifier)),
+        }
+    }
+
+    /// `Some(None)` if the relation has the column but it is computed.
+    fn column(&self, name: &str, identifiers: &IdentifierRules) -> Option<Option<Column>> {
+        match self {
+            ScopeEntry::Table { table, .. } => table
+                .columns
+                .iter()
+                .find(|column| identifiers.same_column(&column.name, name))
+                .map(|column| Some(column.to_owned())),
+            ScopeEntry::Subquery { columns, .. } => columns
+                .iter()
+                .find(|(label, _)| identifiers.same_column(label, name))
+                .map(|(_, column)| column.to_owned()),
+        }
+    }
+}
+
+/// Binds the table aliases and column references of a `QueryDescription` to the schema.
+pub struct Resolver<'a> {
+    scope: Vec<ScopeEntry<'a>>,
+    tables: Vec<&'a Table>,
+    identifiers: IdentifierRules,
+}
+
+fn find_table<'a>(
+    name: &str,
+    existing: &'a [Table],
+    identifiers: &IdentifierRules,
+) -> Result<&'a Table, String> {
+    existing
+        .iter()
+        .find(|table| identifiers.same_table(&table.name, name))
+        .ok_or(format!("Unknown table: {name}"))
+}
+
+/// The output columns of a derived table, plain column references are resolved
+/// to the schema column they pass through.
+fn subquery_columns(
+    query: &QueryDescription,
+    existing: &[Table],
+    identifiers: IdentifierRules,
+) -> Result<Vec<(String, Option<Column>)>, String> {
+    let resolver = Resolver::new(query, existing, identifiers)?;
+    let mut columns: Vec<(String, Option<Column>)> = vec![];
+    for projection in query.projections.iter() {
+        if projection.wildcard {
+            for entry in resolver.scope.iter() {
+                if let ScopeEntry::Table { table, .. } = entry {
+                    columns.extend(
+                        table
+                            .columns
+                            .iter()
+                            .map(|column| (column.name.to_owned(), Some(column.to_owned()))),
+                    );
+                }
+            }
+            continue;
+        }
+        let (label, column) = match &projection.column {
+            Some(column) => (column.name.to_owned(), resolver.column(column).ok()),
+            None => (projection.expression.text.to_owned(), None),
+        };
+        columns.push((projection.alias.to_owned().unwrap_or(label), column));
+    }
+    Ok(columns)
+}
+
+impl<'a> Resolver<'a> {
+    /// Builds the scope of `description` from its FROM clause and joins, names are
+    /// matched following `identifiers`.
+    ///
+    /// # Errors
+    /// Returns an error if the query reads a table that is not in `existing`.
+    pub fn new(
+        description: &QueryDescription,
+        existing: &'a [Table],
+        identifiers: IdentifierRules,
+    ) -> Result<Self, String> {
+        let mut scope: Vec<ScopeEntry<'a>> = vec![];
+        let relations = description
+            .from
+            .iter()
+            .chain(description.joins.iter().map(|join| &join.relation));
+        for relation in relations {
+            match relation {
+                Relation::Table { name, alias } => scope.push(ScopeEntry::Table {
+                    reference: alias.to_owned().unwrap_or(name.to_owned()),
+                    table: find_table(name, existing, &identifiers)?,
+                }),
+                Relation::Subquery { query, alias } => scope.push(ScopeEntry::Subquery {
+                    reference: alias.to_owned(),
+                    columns: subquery_columns(query, existing, identifiers)?,
+                }),
+            }
+        }
+        let tables = description
+            .tables()
+            .iter()
+            .map(|name| find_table(name, existing, &identifiers))
+            .collect::<Result<Vec<&Table>, String>>()?;
+        Ok(Resolver {
+            scope,
+            tables,
+            identifiers,
+        })
+    }
+
+    /// All schema tables the query reads, including the ones read by subqueries.
+    pub fn tables(&self) -> Vec<Table> {
+        self.tables.iter().map(|&table| table.to_owned()).collect()
+    }
+
+    /// Resolves a column reference to the schema column it reads.
+    ///
+    /// # Errors
+    /// Returns an error if the reference is unknown, ambiguous or refers to a computed
+    /// column of a subquery.
+    pub fn column(&self, column_ref: &ColumnRef) -> Result<Column, String> {
+        let mut candidates = self
+            .scope
+            .iter()
+            .filter(|entry| match &column_ref.table {
+                Some(qualifier) => entry.is_referenced_by(qualifier, &self.identifiers),
+                None => true,
+            })
+            .filter_map(|entry| entry.column(&column_ref.name, &self.identifiers));
+        match (candidates.next(), candidates.next()) {
+            (Some(Some(column)), None) => Ok(column),
+            (Some(None), None) => Err(format!(
+                "Column {column_ref} is computed by a subquery and has no sensitivity"
+            )),
+            (Some(_), Some(_)) => Err(format!("Ambiguous column: {column_ref}")),
+            (None, _) => Err(format!("Unknown column: {column_ref}")),
+        }
+    }
+
+    /// Finds the schema table `column_ref` reads among `relations`, positions in the FROM
+    /// clause followed by the joins.
+    ///
+    /// # Returns
+    /// The position of the relation, its table and the column, `None` if the reference is
+    /// unknown or ambiguous among `relations` or reads a subquery.
+    pub fn table_column(
+        &self,
+        column_ref: &ColumnRef,
+        relations: Range<usize>,
+    ) -> Option<(usize, &'a Table, Column)> {
+        let mut candidates = self
+            .scope
+            .iter()
+            .enumerate()
+            .filter(|(position, _)| relations.contains(position))
+            .filter(|(_, entry)| match &column_ref.table {
+                Some(qualifier) => entry.is_referenced_by(qualifier, &self.identifiers),
+                None => true,
+            })
+            .filter_map(|(position, entry)| match entry {
+                ScopeEntry::Table { table, .. } => entry
+                    .column(&column_ref.name, &self.identifiers)
+                    .flatten()
+                    .map(|column| (position, *table, column)),
+                ScopeEntry::Subquery { .. } => None,
+            });
+        match (candidates.next(), candidates.next()) {
+            (Some(found), None) => Some(found),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resolver;
+    use crate::database::identifiers::IdentifierRules;
+    use crate::database::schema::{Column, PrivacyBudget, Table};
+    use crate::query::analyzer::{ColumnRef, SqlAnalyzer};
+
+    fn table(name: &str, columns: &[&str]) -> Table {
+        Table {
+            name: name.to_string(),
+            columns: columns
+                .iter()
+                .map(|column| Column {
+                    name: column.to_string(),
+                    ctype: "Integer".to_string(),
+                    sensitivity: 1.0,
+                    bounds: None,
+                    usage: None,
+                    table_name: name.to_string(),
+                })
+                .collect(),
+            privacy_budget: PrivacyBudget::default(),
+            privacy_unit: None,
+        }
+    }
+
+    fn column_ref(table: Option<&str>, name: &str) -> ColumnRef {
+        ColumnRef {
+            table: table.map(|table| table.to_string()),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn aliases() {
+        let existing = vec![
+            table("Users", &["userId", "age"]),
+            table("Models", &["modelId", "userId"]),
+        ];
+        let description = SqlAnalyzer::new("SELECT sum(u.age) FROM USERS u, MODELS m;")
+            .describe()
+            .unwrap();
+        let resolver = Resolver::new(&description, &existing, IdentifierRules::sqlite()).unwrap();
+
+        let tables: Vec<String> = resolver.tables().into_iter().map(|t| t.name).collect();
+        assert_eq!(["Users", "Models"].to_vec(), tables);
+
+        let column = resolver.column(&column_ref(Some("u"), "age")).unwrap();
+        assert_eq!(
+            ("Users", "age"),
+            (column.table_name.as_str(), column.name.as_str())
+        );
+
+        let column = resolver.column(&column_ref(Some("m"), "userid")).unwrap();
+        assert_eq!("Models", column.table_name);
+
+        assert!(resolver.column(&column_ref(None, "userid")).is_err());
+        assert!(resolver.column(&column_ref(None, "salary")).is_err());
+    }
+
+    #[test]
+    fn subquery_columns() {
+        let existing = vec![table("Users", &["userId", "age"])];
+        let description = SqlAnalyzer::new(
+            "SELECT sum(t.years) FROM (SELECT age AS years, age + 1 FROM users) t;",
+        )
+        .describe()
+        .unwrap();
+        let resolver = Resolver::new(&description, &existing, IdentifierRules::sqlite()).unwrap();
+        let column = resolver.column(&column_ref(Some("t"), "years")).unwrap();
+        assert_eq!(
+            ("Users", "age"),
+            (column.table_name.as_str(), column.name.as_str())
+        );
+    }
+
+    #[test]
+    fn unknown_table() {
+        let description = SqlAnalyzer::new("SELECT sum(age) FROM employees;")
+            .describe()
+            .unwrap();
+        assert!(Resolver::new(
+            &description,
+            &[table("Users", &["age"])],
+            IdentifierRules::sqlite(),
+        )
+        .is_err());
+    }
+}
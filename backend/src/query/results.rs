@@ -0,0 +1,55 @@
+use serde::Serialize;
+
+/// What a column of the result holds.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ColumnKind {
+    /// A GROUP BY key, released as it is once partition selection kept its group.
+    GroupKey,
+    /// A noised aggregate, e.g. `sum`.
+    Aggregate { function: String },
+}
+
+/// Metadata of a column of the result, columns are ordered as in the SELECT list.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResultColumn {
+    pub label: String,
+    #[serde(flatten)]
+    pub kind: ColumnKind,
+}
+
+/// A noised aggregate together with how it was noised.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NoisedValue {
+    pub value: f64,
+    /// The name of the mechanism that drew the noise.
+    pub mechanism: String,
+    /// The scale of the noise, e.g. b for Laplace or sigma for Gaussian noise. `None` if
+    /// it depends on the data, the scale of a joined aggregate would reveal its key
+    /// frequencies.
+    pub scale: Option<f64>,
+    pub epsilon: f64,
+    pub delta: f64,
+}
+
+/// A value of a result row.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum ResultValue {
+    Key(String),
+    Noised(NoisedValue),
+}
+
+/// The typed result of a query, every row holds a value per column in the same order.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct ResultSet {
+    pub columns: Vec<ResultColumn>,
+    pub rows: Vec<Vec<ResultValue>>,
+}
+
+impl ResultSet {
+    /// The position of the column returned under `label`.
+    pub fn column_index(&self, label: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column.label == label)
+    }
+}
@@ -0,0 +1,491 @@
+use crate::database::database::SupportedDatabases;
+use crate::database::schema::{ColumnBounds, PrivacyUnit};
+use sqlparser::ast::{
+    Expr, FunctionArg, FunctionArgExpr, FunctionArguments, GroupByExpr, Ident, Query, Select,
+    SelectItem, SetExpr, Statement, TableFactor, Value,
+};
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::Parser;
+
+/// Label of the row count added to grouped queries, the count feeds partition selection
+/// and is never released.
+pub const PARTITION_SIZE_LABEL: &str = "__partition_size";
+
+/// Prefix of the labels the GROUP BY expressions are returned under for quantiles,
+/// followed by their position.
+pub const GROUP_KEY_PREFIX: &str = "__group_key_";
+
+/// Label of the values a quantile is computed over.
+pub const QUANTILE_VALUE_LABEL: &str = "__quantile_value";
+
+/// Prefix of the labels of the sums AVG, VARIANCE and STDDEV are released from, see
+/// `moment_label`.
+pub const MOMENT_PREFIX: &str = "__moment_";
+
+/// Label of the rank of a row among the rows of its privacy unit in its group.
+pub const UNIT_ROW_LABEL: &str = "__unit_row";
+
+/// Label of the rank of a group among the groups of a privacy unit.
+pub const UNIT_GROUP_LABEL: &str = "__unit_group";
+
+/// Label of the max frequency of a join key, see `key_frequency`.
+pub const KEY_FREQUENCY_LABEL: &str = "__key_frequency";
+
+fn select_mut(query: &mut Query) -> Result<&mut Select, String> {
+    match query.body.as_mut() {
+        SetExpr::Select(select) => Ok(select),
+        SetExpr::Query(query) => select_mut(query),
+        body => Err(format!("Unsupported query: {body}")),
+    }
+}
+
+fn parse_expr(sql: &str) -> Result<Expr, String> {
+    Parser::new(&GenericDialect {})
+        .try_with_sql(sql)
+        .and_then(|mut parser| parser.parse_expr())
+        .map_err(|e| e.to_string())
+}
+
+fn group_by(select: &Select) -> Result<&[Expr], String> {
+    match &select.group_by {
+        GroupByExpr::Expressions(exprs) => Ok(exprs),
+        GroupByExpr::All => Err("GROUP BY ALL is not supported".to_string()),
+    }
+}
+
+/// The label the GROUP BY expression at `position` is returned under.
+pub fn group_key_label(position: usize) -> String {
+    format!("{GROUP_KEY_PREFIX}{position}")
+}
+
+/// The label the `component` (`count`, `sum` or `squares`) of the aggregate at `position`
+/// is returned under.
+pub fn moment_label(component: &str, position: usize) -> String {
+    format!("{MOMENT_PREFIX}{component}_{position}")
+}
+
+/// Adds `COUNT(*) AS __partition_size` to the SELECT list of `query`, or the count of
+/// distinct `units` if the rows are about privacy units.
+///
+/// # Errors
+/// Returns an error if the body of `query` is not a plain SELECT.
+pub fn add_partition_size(query: &mut Query, units: Option<&str>) -> Result<(), String> {
+    let count = match units {
+        Some(units) => format!("COUNT(DISTINCT {units})"),
+        None => "COUNT(*)".to_string(),
+    };
+    let select = select_mut(query)?;
+    select.projection.push(SelectItem::ExprWithAlias {
+        expr: parse_expr(&count)?,
+        alias: Ident::new(PARTITION_SIZE_LABEL),
+    });
+    Ok(())
+}
+
+/// Caps what every privacy unit contributes to `query`: at most `unit.max_rows` rows of
+/// each group, drawn at random, and the rows of at most `unit.max_groups` groups, the
+/// first ones in the order of their keys.
+///
+/// The table is replaced with a subquery ranking its rows with window functions, the
+/// WHERE clause is moved into the subquery so that the cap applies to the rows it keeps.
+///
+/// # Returns
+/// The privacy unit column as the rewritten query refers to it, e.g. `u.user_id`.
+///
+/// # Errors
+/// Returns an error unless `query` is a plain SELECT over a single table.
+pub fn bound_contributions(
+    query: &mut Query,
+    unit: &PrivacyUnit,
+    flavour: SupportedDatabases,
+) -> Result<String, String> {
+    let select = select_mut(query)?;
+    let relation = match select.from.as_slice() {
+        [from] if from.joins.is_empty() => &from.relation,
+        _ => {
+            return Err(
+                "Privacy units are only supported on queries over a single table".to_string(),
+            )
+        }
+    };
+    let reference = match relation {
+        TableFactor::Table {
+            alias: Some(alias), ..
+        } => alias.name.to_string(),
+        TableFactor::Table { name, .. } => name
+            .0
+            .last()
+            .map(|ident| ident.to_string())
+            .ok_or("The query reads an unnamed table")?,
+        _ => {
+            return Err(
+                "Privacy units are only supported on queries over a single table".to_string(),
+            )
+        }
+    };
+    let units = format!("{reference}.{}", Ident::with_quote('`', &unit.column));
+    let random = match flavour {
+        SupportedDatabases::MySQL => "RAND()",
+        SupportedDatabases::SQLite => "RANDOM()",
+    };
+    let keys: Vec<String> = group_by(select)?
+        .iter()
+        .map(|key| key.to_string())
+        .collect();
+    let partition: Vec<&str> = std::iter::once(units.as_str())
+        .chain(keys.iter().map(String::as_str))
+        .collect();
+    let mut ranked = format!(
+        "SELECT {reference}.*, ROW_NUMBER() OVER (PARTITION BY {} ORDER BY {random}) AS {UNIT_ROW_LABEL}",
+        partition.join(", ")
+    );
+    let mut kept = format!("{reference}.{UNIT_ROW_LABEL} <= {}", unit.max_rows);
+    if !keys.is_empty() {
+        ranked += &format!(
+            ", DENSE_RANK() OVER (PARTITION BY {units} ORDER BY {}) AS {UNIT_GROUP_LABEL}",
+            keys.join(", ")
+        );
+        kept += &format!(" AND {reference}.{UNIT_GROUP_LABEL} <= {}", unit.max_groups);
+    }
+    ranked += &format!(" FROM {relation}");
+    if let Some(selection) = &select.selection {
+        ranked += &format!(" WHERE {selection}");
+    }
+    let statements = Parser::parse_sql(
+        &GenericDialect {},
+        &format!("SELECT 1 FROM ({ranked}) AS {reference}"),
+    )
+    .map_err(|e| e.to_string())?;
+    let Some(Statement::Query(mut bounded)) = statements.into_iter().next() else {
+        return Err("Unable to bound the contributions of privacy units".to_string());
+    };
+    select.from = std::mem::take(&mut select_mut(&mut bounded)?.from);
+    select.selection = Some(parse_expr(&kept)?);
+    Ok(units)
+}
+
+/// Adds every GROUP BY expression of `query` to its SELECT list, labelled `__group_key_0`,
+/// `__group_key_1`, ... so that the rows of `quantile_values` can be matched to its groups.
+///
+/// # Errors
+/// Returns an error if the body of `query` is not a plain SELECT.
+pub fn add_group_keys(query: &mut Query) -> Result<(), String> {
+    let select = select_mut(query)?;
+    let keys: Vec<SelectItem> = group_by(select)?
+        .iter()
+        .enumerate()
+        .map(|(position, expr)| SelectItem::ExprWithAlias {
+            expr: expr.clone(),
+            alias: Ident::new(group_key_label(position)),
+        })
+        .collect();
+    select.projection.extend(keys);
+    Ok(())
+}
+
+/// Replaces the aggregate at `position` of the SELECT list with `NULL AS label`, the
+/// database never computes an aggregate the pipeline answers from `quantile_values`.
+///
+/// # Errors
+/// Returns an error if the body of `query` is not a plain SELECT or has no such item.
+pub fn mask_aggregate(query: &mut Query, position: usize, label: &str) -> Result<(), String> {
+    let select = select_mut(query)?;
+    let item = select
+        .projection
+        .get_mut(position)
+        .ok_or_else(|| format!("The query has no column {position}"))?;
+    // Backticks quote identifiers in both MySQL and SQLite.
+    *item = SelectItem::ExprWithAlias {
+        expr: Expr::Value(Value::Null),
+        alias: Ident::with_quote('`', label),
+    };
+    Ok(())
+}
+
+fn first_argument_mut(expr: &mut Expr) -> Option<&mut Expr> {
+    match expr {
+        Expr::Nested(inner) => first_argument_mut(inner),
+        Expr::Function(function) => match &mut function.args {
+            FunctionArguments::List(list) => match list.args.first_mut() {
+                Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(arg)))
+                | Some(FunctionArg::Named {
+                    arg: FunctionArgExpr::Expr(arg),
+                    ..
+                }) => Some(arg),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The argument of the aggregate at `position` of the SELECT list.
+fn aggregate_argument(select: &mut Select, position: usize) -> Result<&mut Expr, String> {
+    match select.projection.get_mut(position) {
+        Some(SelectItem::UnnamedExpr(expr)) | Some(SelectItem::ExprWithAlias { expr, .. }) => {
+            first_argument_mut(expr)
+        }
+        _ => None,
+    }
+    .ok_or_else(|| format!("Column {position} of the query is not an aggregate"))
+}
+
+/// Clamps the argument of the aggregate at `position` of the SELECT list into `bounds`,
+/// with `MIN(MAX(..))` on SQLite and `LEAST(GREATEST(..))` on MySQL. NULLs stay NULL.
+///
+/// # Errors
+/// Returns an error if the body of `query` is not a plain SELECT or the item is not an
+/// aggregate over an expression.
+pub fn clamp_argument(
+    query: &mut Query,
+    position: usize,
+    bounds: &ColumnBounds,
+    flavour: SupportedDatabases,
+) -> Result<(), String> {
+    let argument = aggregate_argument(select_mut(query)?, position)?;
+    *argument = clamp(argument.clone(), bounds, flavour)?;
+    Ok(())
+}
+
+/// `argument` clamped into `bounds` in the dialect of `flavour`.
+fn clamp(
+    argument: Expr,
+    bounds: &ColumnBounds,
+    flavour: SupportedDatabases,
+) -> Result<Expr, String> {
+    let (lower_function, upper_function) = match flavour {
+        SupportedDatabases::MySQL => ("GREATEST", "LEAST"),
+        SupportedDatabases::SQLite => ("MAX", "MIN"),
+    };
+    let mut clamped = parse_expr(&format!(
+        "{upper_function}({lower_function}(NULL, {}), {})",
+        bounds.lower, bounds.upper
+    ))?;
+    let inner = first_argument_mut(&mut clamped)
+        .and_then(first_argument_mut)
+        .ok_or("Unable to clamp the argument")?;
+    *inner = argument;
+    Ok(clamped)
+}
+
+/// Replaces the aggregate at `position` of the SELECT list with `NULL AS label` and adds
+/// what AVG, VARIANCE and STDDEV are released from: the count of its argument, the sum of
+/// the argument clamped into `bounds` and centred on `midpoint` and, with `squares`, the
+/// sum of the squares of the centred values, labelled following `moment_label`.
+///
+/// # Errors
+/// Returns an error if the body of `query` is not a plain SELECT or the item is not an
+/// aggregate over an expression.
+pub fn add_moments(
+    query: &mut Query,
+    position: usize,
+    label: &str,
+    bounds: &ColumnBounds,
+    midpoint: f64,
+    squares: bool,
+    flavour: SupportedDatabases,
+) -> Result<(), String> {
+    let argument = aggregate_argument(select_mut(query)?, position)?.clone();
+    let centred = format!(
+        "({} - {midpoint})",
+        clamp(argument.clone(), bounds, flavour)?
+    );
+    let mut sums = vec![
+        ("count", format!("COUNT({argument})")),
+        ("sum", format!("SUM({centred})")),
+    ];
+    if squares {
+        sums.push(("squares", format!("SUM({centred} * {centred})")));
+    }
+    mask_aggregate(query, position, label)?;
+    let select = select_mut(query)?;
+    for (component, sum) in sums {
+        select.projection.push(SelectItem::ExprWithAlias {
+            expr: parse_expr(&sum)?,
+            alias: Ident::new(moment_label(component, position)),
+        });
+    }
+    Ok(())
+}
+
+/// A query returning, for every row the aggregate at `position` of the SELECT list of
+/// `query` is computed over, its argument as `__quantile_value` and its group as
+/// `__group_key_0`, `__group_key_1`, ...
+///
+/// The values are only ever fed to the exponential mechanism, they are never released.
+///
+/// # Errors
+/// Returns an error if the body of `query` is not a plain SELECT or the item is not an
+/// aggregate over an expression.
+pub fn quantile_values(query: &Query, position: usize) -> Result<Query, String> {
+    let mut values = query.clone();
+    values.order_by.clear();
+    values.limit = None;
+    values.limit_by.clear();
+    values.offset = None;
+    values.fetch = None;
+    let select = select_mut(&mut values)?;
+    let argument = aggregate_argument(select, position)?.clone();
+    let mut projection: Vec<SelectItem> = group_by(select)?
+        .iter()
+        .enumerate()
+        .map(|(position, expr)| SelectItem::ExprWithAlias {
+            expr: expr.clone(),
+            alias: Ident::new(group_key_label(position)),
+        })
+        .collect();
+    projection.push(SelectItem::ExprWithAlias {
+        expr: argument,
+        alias: Ident::new(QUANTILE_VALUE_LABEL),
+    });
+    select.projection = projection;
+    select.distinct = None;
+    select.group_by = GroupByExpr::Expressions(vec![]);
+    Ok(values)
+}
+
+/// A query for the max frequency of `column` in `table`, the largest number of rows that
+/// share one of its values, as `__key_frequency`. No row is returned for an empty table.
+///
+/// NULLs are left out, they never join. The frequency only bounds the noise of joins, it
+/// is never released.
+pub fn key_frequency(table: &str, column: &str) -> String {
+    let column = Ident::with_quote('`', column);
+    format!(
+        "SELECT COUNT(*) AS {KEY_FREQUENCY_LABEL} FROM {} WHERE {column} IS NOT NULL \
+        GROUP BY {column} ORDER BY {KEY_FREQUENCY_LABEL} DESC LIMIT 1",
+        Ident::with_quote('`', table)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_group_keys, add_moments, add_partition_size, bound_contributions, clamp_argument,
+        key_frequency, mask_aggregate, quantile_values,
+    };
+    use crate::database::database::SupportedDatabases;
+    use crate::database::schema::{ColumnBounds, PrivacyUnit};
+    use crate::query::analyzer::SqlAnalyzer;
+
+    #[test]
+    fn partition_size() {
+        let mut query = SqlAnalyzer::new("SELECT name, sum(age) FROM users GROUP BY name")
+            .query()
+            .unwrap();
+        add_partition_size(&mut query, None).unwrap();
+        assert_eq!(
+            "SELECT name, sum(age), COUNT(*) AS __partition_size FROM users GROUP BY name",
+            query.to_string()
+        );
+    }
+
+    #[test]
+    fn quantiles() {
+        let mut query = SqlAnalyzer::new(
+            "SELECT name, median(age) FROM users WHERE age > 1 GROUP BY name ORDER BY name LIMIT 3",
+        )
+        .query()
+        .unwrap();
+        assert_eq!(
+            "SELECT name AS __group_key_0, age AS __quantile_value FROM users WHERE age > 1",
+            quantile_values(&query, 1).unwrap().to_string()
+        );
+        assert!(quantile_values(&query, 0).is_err());
+        mask_aggregate(&mut query, 1, "median(age)").unwrap();
+        add_group_keys(&mut query).unwrap();
+        assert_eq!(
+            "SELECT name, NULL AS `median(age)`, name AS __group_key_0 FROM users WHERE age > 1 GROUP BY name ORDER BY name LIMIT 3",
+            query.to_string()
+        );
+    }
+
+    #[test]
+    fn clamped_arguments() {
+        let bounds = ColumnBounds::new(-1.0, 1000.5).unwrap();
+        for (flavour, expected) in [
+            (
+                SupportedDatabases::SQLite,
+                "SELECT name, sum(MIN(MAX(salary, -1), 1000.5)) FROM users GROUP BY name",
+            ),
+            (
+                SupportedDatabases::MySQL,
+                "SELECT name, sum(LEAST(GREATEST(salary, -1), 1000.5)) FROM users GROUP BY name",
+            ),
+        ] {
+            let mut query = SqlAnalyzer::new("SELECT name, sum(salary) FROM users GROUP BY name")
+                .query()
+                .unwrap();
+            clamp_argument(&mut query, 1, &bounds, flavour).unwrap();
+            assert_eq!(expected, query.to_string());
+            assert!(clamp_argument(&mut query, 0, &bounds, flavour).is_err());
+        }
+    }
+
+    #[test]
+    fn moments() {
+        let mut query = SqlAnalyzer::new("SELECT stddev(age) FROM users")
+            .query()
+            .unwrap();
+        let bounds = ColumnBounds::new(0.0, 100.0).unwrap();
+        add_moments(
+            &mut query,
+            0,
+            "stddev(age)",
+            &bounds,
+            50.0,
+            true,
+            SupportedDatabases::MySQL,
+        )
+        .unwrap();
+        assert_eq!(
+            "SELECT NULL AS `stddev(age)`, COUNT(age) AS __moment_count_0, \
+            SUM((LEAST(GREATEST(age, 0), 100) - 50)) AS __moment_sum_0, \
+            SUM((LEAST(GREATEST(age, 0), 100) - 50) * (LEAST(GREATEST(age, 0), 100) - 50)) AS __moment_squares_0 \
+            FROM users",
+            query.to_string()
+        );
+    }
+
+    #[test]
+    fn contributions() {
+        let mut query = SqlAnalyzer::new(
+            "SELECT u.name, count(u.age) FROM users AS u WHERE u.age > 18 GROUP BY u.name",
+        )
+        .query()
+        .unwrap();
+        let unit = PrivacyUnit {
+            column: "userId".to_string(),
+            max_rows: 2,
+            max_groups: 3,
+        };
+        let units = bound_contributions(&mut query, &unit, SupportedDatabases::SQLite).unwrap();
+        assert_eq!("u.`userId`", units);
+        add_partition_size(&mut query, Some(&units)).unwrap();
+        assert_eq!(
+            "SELECT u.name, count(u.age), COUNT(DISTINCT u.`userId`) AS __partition_size \
+            FROM (SELECT u.*, \
+            ROW_NUMBER() OVER (PARTITION BY u.`userId`, u.name ORDER BY RANDOM()) AS __unit_row, \
+            DENSE_RANK() OVER (PARTITION BY u.`userId` ORDER BY u.name) AS __unit_group \
+            FROM users AS u WHERE u.age > 18) AS u \
+            WHERE u.__unit_row <= 2 AND u.__unit_group <= 3 GROUP BY u.name",
+            query.to_string()
+        );
+
+        let mut query =
+            SqlAnalyzer::new("SELECT count(*) FROM users JOIN orders ON users.id = orders.user_id")
+                .query()
+                .unwrap();
+        assert!(bound_contributions(&mut query, &unit, SupportedDatabases::MySQL).is_err());
+    }
+
+    #[test]
+    fn key_frequencies() {
+        assert_eq!(
+            "SELECT COUNT(*) AS __key_frequency FROM `visits` WHERE `user id` IS NOT NULL \
+            GROUP BY `user id` ORDER BY __key_frequency DESC LIMIT 1",
+            key_frequency("visits", "user id")
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use crate::query::analyzer::{Expression, Projection, QueryDescription, Relation};
+
+/// Whether `projection` selects one of the GROUP BY expressions, either as written or by its alias.
+fn is_group_key(projection: &Projection, description: &QueryDescription) -> bool {
+    if projection.wildcard || projection.aggregate.is_some() {
+        return false;
+    }
+    description.group_by.iter().any(|key| {
+        (key.columns == projection.expression.columns
+            && key.text.eq_ignore_ascii_case(&projection.expression.text))
+            || projection
+                .alias
+                .as_deref()
+                .is_some_and(|alias| alias.eq_ignore_ascii_case(&key.text))
+    })
+}
+
+/// Whether `expression` is one of the GROUP BY expressions or the alias of a selected one,
+/// ordering by them does not depend on the aggregates.
+fn is_group_key_expression(expression: &Expression, description: &QueryDescription) -> bool {
+    description.group_by.iter().any(|key| {
+        key.columns == expression.columns && key.text.eq_ignore_ascii_case(&expression.text)
+    }) || description.projections.iter().any(|projection| {
+        is_group_key(projection, description)
+            && projection
+                .alias
+                .as_deref()
+                .is_some_and(|alias| alias.eq_ignore_ascii_case(&expression.text))
+    })
+}
+
+/// The projections that would release something other than an aggregate or a group key,
+/// e.g. raw column values, `*` or the result of a scalar subquery.
+fn row_level_projections(description: &QueryDescription) -> Vec<String> {
+    description
+        .projections
+        .iter()
+        .filter(|projection| projection.aggregate.is_none() || projection.wildcard)
+        .filter(|projection| !is_group_key(projection, description))
+        .map(|projection| projection.expression.text.to_owned())
+        .collect()
+}
+
+/// Checks a query before any budget is spent on it or the database is queried.
+///
+/// # Errors
+/// Returns an error if the query has a subquery, a WITH clause or a derived table, a
+/// single row can change which rows those select and the sensitivity of the aggregates
+/// no longer bounds the release.
+///
+/// Returns an error naming the offending expressions if the query returns row-level data,
+/// if it filters groups on exact aggregates with HAVING, or if it picks the groups it
+/// returns with LIMIT or by ordering on anything but the group keys, either runs on the
+/// exact aggregates before they are noised.
+pub fn validate(description: &QueryDescription) -> Result<(), String> {
+    if !description.subqueries.is_empty() {
+        return Err("Subqueries and WITH clauses are not supported".to_string());
+    }
+    let derived = description
+        .from
+        .iter()
+        .chain(description.joins.iter().map(|join| &join.relation))
+        .any(|relation| matches!(relation, Relation::Subquery { .. }));
+    if derived {
+        return Err("Derived tables in FROM are not supported".to_string());
+    }
+    if let Some(having) = &description.having {
+        return Err(format!(
+            "HAVING filters groups on exact aggregates and is not supported: {}",
+            having.text
+        ));
+    }
+    if let Some(limit) = &description.limit {
+        return Err(format!(
+            "{limit} picks rows on exact aggregates and is not supported"
+        ));
+    }
+    let ordering: Vec<&str> = description
+        .order_by
+        .iter()
+        .filter(|expression| !is_group_key_expression(expression, description))
+        .map(|expression| expression.text.as_str())
+        .collect();
+    if !ordering.is_empty() {
+        return Err(format!(
+            "Only group keys can be ordered by, these expressions are ordered on exact aggregates: {}",
+            ordering.join(", ")
+        ));
+    }
+    let row_level = row_level_projections(description);
+    if !row_level.is_empty() {
+        return Err(format!(
+            "Only aggregates can be selected, these expressions would return row-level data: {}",
+            row_level.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate;
+    use crate::query::analyzer::SqlAnalyzer;
+
+    fn validate_sql(sql: &str) -> Result<(), String> {
+        validate(&SqlAnalyzer::new(sql).describe().unwrap())
+    }
+
+    #[test]
+    fn row_level_data() {
+        assert!(validate_sql("SELECT sum(age), count(*) FROM users;").is_ok());
+
+        let error = validate_sql("SELECT name, sum(age), age + 1 FROM users;").unwrap_err();
+        assert!(error.ends_with(": name, age + 1"));
+        assert!(validate_sql("SELECT * FROM users;").is_err());
+        assert!(validate_sql("SELECT sum(age) + 1 FROM users;").is_err());
+        assert!(validate_sql("SELECT (SELECT max(age) FROM users);").is_err());
+    }
+
+    #[test]
+    fn subqueries() {
+        for sql in [
+            "SELECT count(age) FROM users WHERE EXISTS (SELECT 1 FROM users WHERE salary = 3);",
+            "SELECT count(age) FROM users WHERE age > (SELECT avg(age) FROM users);",
+            "WITH old AS (SELECT age FROM users) SELECT count(age) FROM users;",
+            "SELECT sum(x) FROM (SELECT age AS x FROM users) t;",
+            "SELECT count(age) FROM users JOIN (SELECT id FROM users) t ON t.id = users.id;",
+        ] {
+            assert!(validate_sql(sql).is_err(), "{sql}");
+        }
+    }
+
+    #[test]
+    fn group_keys() {
+        assert!(validate_sql("SELECT Name, sum(age) FROM users GROUP BY name;").is_ok());
+        assert!(
+            validate_sql("SELECT age / 10 AS decade, count(*) FROM users GROUP BY decade;").is_ok()
+        );
+        assert!(validate_sql("SELECT name, salary, sum(age) FROM users GROUP BY name;").is_err());
+        assert!(validate_sql(
+            "SELECT name, sum(age) FROM users GROUP BY name HAVING count(*) > 5;"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(
+            validate_sql("SELECT name, sum(age) FROM users GROUP BY name ORDER BY name;").is_ok()
+        );
+        assert!(validate_sql(
+            "SELECT age / 10 AS decade, count(*) FROM users GROUP BY decade ORDER BY decade DESC;"
+        )
+        .is_ok());
+        assert!(validate_sql(
+            "SELECT name, count(*) FROM users GROUP BY name ORDER BY count(*) DESC;"
+        )
+        .is_err());
+        assert!(validate_sql("SELECT name, count(*) FROM users GROUP BY name LIMIT 1;").is_err());
+        assert!(validate_sql("SELECT sum(age) FROM users ORDER BY age;").is_err());
+    }
+}
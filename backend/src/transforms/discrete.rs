@@ -0,0 +1,283 @@
+/*
+The discrete Laplace (two-sided geometric) and discrete Gaussian distributions, sampled
+exactly (Canonne, Kamath and Steinke, "The Discrete Gaussian for Differential Privacy",
+NeurIPS 2020).
+
+Sampling a geometric variable as `floor(ln(u) / ln(alpha))` on doubles has the same flaw
+as textbook Laplace sampling, the probabilities of the outputs are those of the floating
+point arithmetic and not those the privacy proof needs. Here `epsilon / sensitivity` is
+taken as a ratio of integers and every draw is a comparison of uniform integers, the
+output follows P(x) proportional to exp(-|x| epsilon / sensitivity) exactly.
+*/
+use crate::transforms::validate_epsilon;
+use rand::{Rng, RngCore};
+
+/// Largest denominator of `epsilon / sensitivity`, leaves room for the multiples of it the
+/// sampler computes.
+const MAX_DENOMINATOR_BITS: u32 = 120;
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// `epsilon / sensitivity` as a reduced fraction of integers, `None` for a zero
+/// sensitivity or an infinite epsilon, which call for no noise.
+///
+/// A finite double is `m * 2^e`, the ratio is exact unless the denominator would exceed
+/// 2^120, low bits of `m` are then dropped and the ratio is rounded down, which adds noise.
+fn epsilon_ratio(epsilon: f64, sensitivity: f64) -> Option<(u128, u128)> {
+    let sensitivity = sensitivity.ceil().min(u64::MAX as f64) as u128;
+    if sensitivity == 0 || epsilon.is_infinite() {
+        return None;
+    }
+    let bits = epsilon.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = (bits & ((1 << 52) - 1)) as u128;
+    let (mut mantissa, mut exponent) = match exponent {
+        0 => (fraction, -1074),
+        _ => (fraction | (1 << 52), exponent - 1075),
+    };
+    let trailing = mantissa.trailing_zeros().min(127);
+    mantissa >>= trailing;
+    exponent += trailing as i32;
+    let (numerator, denominator) = if exponent >= 0 {
+        // Above 2^117 the noise is nil anyway, rounding down keeps it a valid epsilon.
+        (mantissa << exponent.min(64), sensitivity)
+    } else {
+        let room = MAX_DENOMINATOR_BITS - (128 - sensitivity.leading_zeros());
+        let shift = (-exponent) as u32;
+        if shift > room {
+            mantissa >>= shift - room;
+        }
+        // Below 2^-120 the noise only ever saturates, the smallest ratio does the same.
+        (mantissa.max(1), sensitivity << shift.min(room))
+    };
+    let divisor = gcd(numerator, denominator);
+    Some((numerator / divisor, denominator / divisor))
+}
+
+/// Bernoulli(exp(-numerator / denominator)) for `numerator <= denominator`.
+fn bernoulli_exp_fraction(numerator: u128, denominator: u128, rng: &mut dyn RngCore) -> bool {
+    // K is the index of the first failure of Bernoulli(gamma / k), k = 1, 2, ..., it is
+    // odd with probability exp(-gamma).
+    let mut k: u128 = 1;
+    loop {
+        let range = denominator.saturating_mul(k);
+        if rng.gen_range(0..range) >= numerator {
+            return k % 2 == 1;
+        }
+        k += 1;
+    }
+}
+
+/// Bernoulli(exp(-1)).
+fn bernoulli_exp_one(rng: &mut dyn RngCore) -> bool {
+    bernoulli_exp_fraction(1, 1, rng)
+}
+
+/// Bernoulli(exp(-numerator / denominator)) for any ratio.
+fn bernoulli_exp(numerator: u128, denominator: u128, rng: &mut dyn RngCore) -> bool {
+    // exp(-gamma) = exp(-1)^floor(gamma) * exp(-(gamma - floor(gamma))).
+    for _ in 0..numerator / denominator {
+        if !bernoulli_exp_one(rng) {
+            return false;
+        }
+    }
+    bernoulli_exp_fraction(numerator % denominator, denominator, rng)
+}
+
+/// Samples from P(x) proportional to exp(-|x| numerator / denominator) over the integers.
+fn discrete_laplace(numerator: u128, denominator: u128, rng: &mut dyn RngCore) -> i64 {
+    loop {
+        // X = U + denominator * V is geometric with P(X) proportional to
+        // exp(-X / denominator), U uniform below the denominator and V geometric.
+        let remainder = rng.gen_range(0..denominator);
+        if !bernoulli_exp_fraction(remainder, denominator, rng) {
+            continue;
+        }
+        let mut multiple: u128 = 0;
+        while bernoulli_exp_one(rng) {
+            multiple += 1;
+        }
+        let Some(geometric) = multiple
+            .checked_mul(denominator)
+            .and_then(|scaled| scaled.checked_add(remainder))
+        else {
+            continue;
+        };
+        let magnitude = geometric / numerator;
+        let negative: bool = rng.gen();
+        // Zero would otherwise come up from both signs.
+        if negative && magnitude == 0 {
+            continue;
+        }
+        let magnitude = i64::try_from(magnitude).unwrap_or(i64::MAX);
+        return if negative { -magnitude } else { magnitude };
+    }
+}
+
+/// Two-sided geometric noise with P(k) proportional to exp(-|k| epsilon / sensitivity).
+///
+/// # Parameters
+/// - `epsilon`: The privacy budget spent on the release.
+/// - `sensitivity`: The sensitivity of the integer valued query, a fractional one is
+///   rounded up.
+/// - `rng`: The source of randomness, see `transforms::rng::NoiseRng`.
+///
+/// # Returns
+/// The noise, 0 for a zero sensitivity.
+///
+/// # Errors
+/// Returns an error unless `epsilon` is positive and finite.
+pub fn geometric_noise(
+    epsilon: f64,
+    sensitivity: f64,
+    rng: &mut dyn RngCore,
+) -> Result<i64, String> {
+    validate_epsilon(epsilon)?;
+    Ok(match epsilon_ratio(epsilon, sensitivity) {
+        Some((numerator, denominator)) => discrete_laplace(numerator, denominator, rng),
+        None => 0,
+    })
+}
+
+/// Discrete Gaussian noise with P(k) proportional to exp(-k^2 / (2 sigma^2)).
+///
+/// # Parameters
+/// - `sigma`: The scale of the noise, an integer so that every draw stays exact.
+/// - `rng`: The source of randomness, see `transforms::rng::NoiseRng`.
+///
+/// # Returns
+/// The noise, 0 for a zero `sigma`.
+pub fn gaussian_noise(sigma: u64, rng: &mut dyn RngCore) -> i64 {
+    if sigma == 0 {
+        return 0;
+    }
+    let variance = sigma as u128 * sigma as u128;
+    let t = sigma as u128 + 1;
+    loop {
+        // Discrete Laplace with scale t = floor(sigma) + 1, accepted with probability
+        // exp(-(|y| - sigma^2 / t)^2 / (2 sigma^2)) = exp(-(|y| t - sigma^2)^2 / (2 sigma^2 t^2)).
+        let noise = discrete_laplace(1, t, rng);
+        // Overflowing needs |y| >= 2^64 / t, which is accepted with probability below
+        // exp(-2^60) for any sigma.
+        let Some(numerator) = (noise.unsigned_abs() as u128)
+            .checked_mul(t)
+            .map(|scaled| scaled.abs_diff(variance))
+            .and_then(|difference| difference.checked_mul(difference))
+        else {
+            continue;
+        };
+        let Some(denominator) = variance
+            .checked_mul(t * t)
+            .and_then(|denominator| denominator.checked_mul(2))
+        else {
+            continue;
+        };
+        if bernoulli_exp(numerator, denominator, rng) {
+            return noise;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bernoulli_exp_one, epsilon_ratio, gaussian_noise, geometric_noise};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn exact_ratio() {
+        assert_eq!(Some((1, 1)), epsilon_ratio(1.0, 1.0));
+        assert_eq!(Some((1, 4)), epsilon_ratio(0.5, 2.0));
+        // Fractional sensitivities are rounded up.
+        assert_eq!(Some((3, 2)), epsilon_ratio(3.0, 1.5));
+        assert_eq!(None, epsilon_ratio(1.0, 0.0));
+        assert_eq!(None, epsilon_ratio(f64::INFINITY, 1.0));
+
+        // 0.1 is not a dyadic fraction, the ratio is the double closest to it.
+        let (numerator, denominator) = epsilon_ratio(0.1, 3.0).unwrap();
+        assert_eq!(0.1 / 3.0, numerator as f64 / denominator as f64);
+
+        // (2^53 - 1) 2^-100 / 2^40 needs a denominator of 2^140, the ratio is rounded down
+        // to (2^53 - 2^21) 2^-140.
+        let epsilon = ((1u64 << 53) - 1) as f64 * 2f64.powi(-100);
+        assert_eq!(
+            Some(((1 << 32) - 1, 1 << 119)),
+            epsilon_ratio(epsilon, 2f64.powi(40))
+        );
+    }
+
+    #[test]
+    fn bernoulli() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let draws = 20_000;
+        let successes = (0..draws).filter(|_| bernoulli_exp_one(&mut rng)).count();
+        let frequency = successes as f64 / draws as f64;
+        assert!((frequency - (-1f64).exp()).abs() < 0.015, "{frequency}");
+    }
+
+    #[test]
+    fn distribution() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let draws = 20_000;
+        let samples: Vec<i64> = (0..draws)
+            .map(|_| geometric_noise(1.0, 1.0, &mut rng).unwrap())
+            .collect();
+        let alpha = (-1f64).exp();
+        let frequency = |value: i64| {
+            samples.iter().filter(|&&sample| sample == value).count() as f64 / draws as f64
+        };
+        // P(k) = (1 - alpha) / (1 + alpha) * alpha^|k|.
+        for value in [-2i64, -1, 0, 1, 2] {
+            let expected = (1.0 - alpha) / (1.0 + alpha) * alpha.powi(value.abs() as i32);
+            let frequency = frequency(value);
+            assert!((frequency - expected).abs() < 0.015, "{value}: {frequency}");
+        }
+        let mean = samples.iter().sum::<i64>() as f64 / draws as f64;
+        assert!(mean.abs() < 0.05, "{mean}");
+
+        assert_eq!(Ok(0), geometric_noise(1.0, 0.0, &mut rng));
+    }
+
+    #[test]
+    fn invalid_epsilon() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for epsilon in [0.0, -0.0, -1.0, f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+            assert!(
+                geometric_noise(epsilon, 1.0, &mut rng).is_err(),
+                "{epsilon}"
+            );
+        }
+    }
+
+    #[test]
+    fn gaussian() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let draws = 20_000;
+        let samples: Vec<i64> = (0..draws).map(|_| gaussian_noise(2, &mut rng)).collect();
+        let weight = |value: i64| (-(value * value) as f64 / 8.0).exp();
+        let total: f64 = (-40i64..=40).map(weight).sum();
+        for value in [-3i64, -1, 0, 1, 2, 4] {
+            let expected = weight(value) / total;
+            let frequency =
+                samples.iter().filter(|&&sample| sample == value).count() as f64 / draws as f64;
+            assert!((frequency - expected).abs() < 0.015, "{value}: {frequency}");
+        }
+        let mean = samples.iter().sum::<i64>() as f64 / draws as f64;
+        assert!(mean.abs() < 0.05, "{mean}");
+
+        // Large scales stay exact and within reach.
+        let sigma = 1u64 << 20;
+        let samples: Vec<f64> = (0..2_000)
+            .map(|_| gaussian_noise(sigma, &mut rng) as f64 / sigma as f64)
+            .collect();
+        let variance = samples.iter().map(|sample| sample * sample).sum::<f64>() / 2_000.0;
+        assert!((variance - 1.0).abs() < 0.1, "{variance}");
+
+        assert_eq!(0, gaussian_noise(0, &mut rng));
+    }
+}
@@ -0,0 +1,125 @@
+/*
+The sensitivity of a column is that of a single table, a row of it moves an aggregate by
+at most the sensitivity. Joined, a row is repeated once for every row of the other table
+it matches, a user in a many-to-many join can move a COUNT by thousands. Joins are
+therefore answered with elastic sensitivity (Johnson, Near and Song 2018).
+
+Equi-joins are bounded by the max frequency mf of their keys, the largest number of rows
+sharing a key value. Joining L and R on L.x = R.y, a row of L matches at most mf(y, R)
+rows of R and a row of R at most mf(x, L), the stability of the join, how many of its
+rows a single row of a table changes, is
+
+    S(L join R) = max(mf(x, L) S(R), mf(y, R) S(L))
+
+where a table has a stability of 1, and the max frequency of a column of L in the join is
+its frequency in L times mf(y, R). If L and R read the same table a row is in both and
+the stability is mf(x, L) S(R) + mf(y, R) S(L) + S(L) S(R).
+
+The frequencies are those of the data, a neighbouring database changes each of them by
+one: S(k), with k added to every max frequency of a table, bounds the stability at
+distance k. The noise is calibrated to the smooth bound
+
+    max over k >= 0 of exp(-beta k) S(k),   beta = epsilon / (2 ln(2 / delta))
+
+Laplace noise of scale 2 S / epsilon times the sensitivity of a row is then
+(epsilon, delta)-DP. S(k) is a polynomial of degree at most the number of joins d with
+non-negative coefficients, exp(-beta k) S(k) decreases once k is above d / beta.
+*/
+
+/// An equi-join of a relation onto the relations before it, in the order they are joined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JoinStep {
+    /// The position of the relation the key on the left is read from, the first relation
+    /// of the FROM clause is 0 and the relation joined by the n-th step is n + 1.
+    pub left: usize,
+    /// The max frequency of the key on the left in its table.
+    pub left_frequency: f64,
+    /// The max frequency of the key of the joined relation in its table.
+    pub right_frequency: f64,
+    /// Whether the joined relation reads a table already read on the left.
+    pub self_join: bool,
+}
+
+/// The elastic stability of the joins at distance `distance`, how many rows of the joined
+/// relation a single row of one of its tables changes.
+pub fn elastic_stability(steps: &[JoinStep], distance: f64) -> f64 {
+    // What the max frequency of a key in each relation is multiplied by in the join.
+    let mut multipliers: Vec<f64> = vec![1.0];
+    let mut stability = 1.0;
+    for step in steps.iter() {
+        let left = (step.left_frequency + distance) * multipliers[step.left];
+        let right = step.right_frequency + distance;
+        stability = if step.self_join {
+            left + right * stability + stability
+        } else {
+            left.max(right * stability)
+        };
+        for multiplier in multipliers.iter_mut() {
+            *multiplier *= right;
+        }
+        multipliers.push(left);
+    }
+    stability
+}
+
+/// The smooth bound on the stability of the joins the noise is calibrated to.
+///
+/// # Parameters
+/// - `steps`: The joins, in the order they are made.
+/// - `epsilon`: The epsilon of the release.
+/// - `delta`: The delta of the release, positive.
+///
+/// # Returns
+/// The largest `exp(-beta k) S(k)`, 1 if there are no joins.
+pub fn smooth_stability(steps: &[JoinStep], epsilon: f64, delta: f64) -> f64 {
+    let beta = epsilon / (2.0 * (2.0 / delta).ln());
+    let furthest = (steps.len() as f64 / beta).ceil() as u64;
+    (0..=furthest)
+        .map(|distance| distance as f64)
+        .map(|distance| (-beta * distance).exp() * elastic_stability(steps, distance))
+        .fold(1.0, f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{elastic_stability, smooth_stability, JoinStep};
+
+    fn step(left: usize, left_frequency: f64, right_frequency: f64) -> JoinStep {
+        JoinStep {
+            left,
+            left_frequency,
+            right_frequency,
+            self_join: false,
+        }
+    }
+
+    #[test]
+    fn stability() {
+        // Every user has one row and up to 5 visits.
+        let one_to_many = [step(0, 1.0, 5.0)];
+        assert_eq!(5.0, elastic_stability(&one_to_many, 0.0));
+        assert_eq!(7.0, elastic_stability(&one_to_many, 2.0));
+
+        // Each of the 5 visits of a user matches up to 3 rows of the third relation.
+        let chain = [step(0, 1.0, 5.0), step(1, 5.0, 3.0)];
+        assert_eq!(15.0, elastic_stability(&chain, 0.0));
+
+        let self_join = [JoinStep {
+            self_join: true,
+            ..step(0, 5.0, 5.0)
+        }];
+        assert_eq!(11.0, elastic_stability(&self_join, 0.0));
+    }
+
+    #[test]
+    fn smoothing() {
+        assert_eq!(1.0, smooth_stability(&[], 1.0, 1e-6));
+        let steps = [step(0, 1.0, 5.0)];
+        let smooth = smooth_stability(&steps, 1.0, 1e-6);
+        // At least the stability of the data, at most what nearby databases can reach.
+        assert!(smooth >= 5.0, "{smooth}");
+        assert!(smooth < elastic_stability(&steps, 100.0), "{smooth}");
+        // Less budget looks further away.
+        assert!(smooth_stability(&steps, 0.1, 1e-6) > smooth);
+    }
+}
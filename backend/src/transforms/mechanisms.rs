@@ -0,0 +1,318 @@
+use crate::database::schema::PrivacyBudget;
+use crate::transforms::{
+    gaussian_sigma, gaussian_transform, geometric_transform, laplace_scale, laplace_transform,
+    validate_epsilon, validate_gaussian_parameters,
+};
+use rand::RngCore;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Name of the mechanism queries are answered with unless they ask for another one.
+pub const DEFAULT_MECHANISM: &str = "laplace";
+
+/// A noise mechanism that can be registered with a `MechanismRegistry` and selected per query.
+pub trait NoiseMechanism: Send + Sync {
+    /// The name the mechanism is registered and selected under.
+    fn name(&self) -> &str;
+
+    /// The (epsilon, delta) a single release costs when the query asks for `epsilon` and `delta`.
+    ///
+    /// # Errors
+    /// Returns an error if the mechanism cannot be calibrated for the requested parameters.
+    fn privacy_cost(&self, epsilon: f64, delta: f64) -> Result<PrivacyBudget, String>;
+
+    /// Releases `true_value` perturbed with noise calibrated to `sensitivity` and `cost`,
+    /// drawing all randomness from `rng`.
+    fn sample(
+        &self,
+        true_value: f64,
+        sensitivity: f64,
+        cost: &PrivacyBudget,
+        rng: &mut dyn RngCore,
+    ) -> f64;
+
+    /// The scale of the noise `sample` adds for `sensitivity` and `cost`.
+    fn scale(&self, sensitivity: f64, cost: &PrivacyBudget) -> f64;
+
+    /// The zCDP parameter of a single release at `cost`, for mechanisms whose guarantee
+    /// composes better in zCDP than in (epsilon, delta)-DP, see `accountant::Release`.
+    fn zcdp_rho(&self, _cost: &PrivacyBudget) -> Option<f64> {
+        None
+    }
+
+    /// Name of the mechanism used instead when the released value can only be an integer.
+    fn integer_counterpart(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Continuous Laplace noise, pure epsilon-DP.
+pub struct LaplaceMechanism;
+
+impl NoiseMechanism for LaplaceMechanism {
+    fn name(&self) -> &str {
+        "laplace"
+    }
+
+    fn privacy_cost(&self, epsilon: f64, _delta: f64) -> Result<PrivacyBudget, String> {
+        validate_epsilon(epsilon)?;
+        Ok(PrivacyBudget::new(epsilon, 0.0))
+    }
+
+    fn sample(
+        &self,
+        true_value: f64,
+        sensitivity: f64,
+        cost: &PrivacyBudget,
+        rng: &mut dyn RngCore,
+    ) -> f64 {
+        laplace_transform(true_value, sensitivity, cost.epsilon, rng)
+    }
+
+    fn scale(&self, sensitivity: f64, cost: &PrivacyBudget) -> f64 {
+        laplace_scale(sensitivity, cost.epsilon)
+    }
+
+    fn integer_counterpart(&self) -> Option<&str> {
+        Some("geometric")
+    }
+}
+
+/// Two-sided geometric noise, pure epsilon-DP over the integers.
+pub struct GeometricMechanism;
+
+impl NoiseMechanism for GeometricMechanism {
+    fn name(&self) -> &str {
+        "geometric"
+    }
+
+    fn privacy_cost(&self, epsilon: f64, _delta: f64) -> Result<PrivacyBudget, String> {
+        validate_epsilon(epsilon)?;
+        Ok(PrivacyBudget::new(epsilon, 0.0))
+    }
+
+    fn sample(
+        &self,
+        true_value: f64,
+        sensitivity: f64,
+        cost: &PrivacyBudget,
+        rng: &mut dyn RngCore,
+    ) -> f64 {
+        // `privacy_cost` refuses the epsilons the transform cannot be calibrated for.
+        geometric_transform(true_value.round() as i64, sensitivity, cost.epsilon, rng)
+            .map_or(f64::NAN, |noised| noised as f64)
+    }
+
+    fn scale(&self, sensitivity: f64, cost: &PrivacyBudget) -> f64 {
+        sensitivity.ceil() / cost.epsilon
+    }
+}
+
+/// Gaussian noise, (epsilon, delta)-DP. A single aggregate is a scalar so its
+/// L1 sensitivity doubles as the L2 sensitivity.
+pub struct GaussianMechanism;
+
+impl NoiseMechanism for GaussianMechanism {
+    fn name(&self) -> &str {
+        "gaussian"
+    }
+
+    fn privacy_cost(&self, epsilon: f64, delta: f64) -> Result<PrivacyBudget, String> {
+        validate_gaussian_parameters(epsilon, delta)?;
+        Ok(PrivacyBudget::new(epsilon, delta))
+    }
+
+    fn sample(
+        &self,
+        true_value: f64,
+        sensitivity: f64,
+        cost: &PrivacyBudget,
+        rng: &mut dyn RngCore,
+    ) -> f64 {
+        gaussian_transform(true_value, sensitivity, cost.epsilon, cost.delta, rng)
+    }
+
+    fn scale(&self, sensitivity: f64, cost: &PrivacyBudget) -> f64 {
+        gaussian_sigma(sensitivity, cost.epsilon, cost.delta)
+    }
+
+    /// Noise with standard deviation sigma over sensitivity D is D^2 / (2 sigma^2)-zCDP.
+    fn zcdp_rho(&self, cost: &PrivacyBudget) -> Option<f64> {
+        let sigma = gaussian_sigma(1.0, cost.epsilon, cost.delta);
+        Some(1.0 / (2.0 * sigma * sigma))
+    }
+}
+
+/// The mechanisms queries can select by name.
+///
+/// `MechanismRegistry::default()` holds the built-in Laplace, geometric and Gaussian
+/// mechanisms, other crates add their own with `with_builtins` or `register`. Names are
+/// unique, a mechanism never silently replaces another one queries already select.
+#[derive(Clone)]
+pub struct MechanismRegistry {
+    mechanisms: HashMap<String, Arc<dyn NoiseMechanism>>,
+}
+
+impl MechanismRegistry {
+    /// Creates a registry without any mechanisms.
+    pub fn new() -> Self {
+        MechanismRegistry {
+            mechanisms: HashMap::new(),
+        }
+    }
+
+    /// Creates a registry holding the built-in mechanisms and `mechanisms`.
+    ///
+    /// # Errors
+    /// Returns an error if two mechanisms share a name, built-in ones included.
+    pub fn with_builtins(
+        mechanisms: impl IntoIterator<Item = Arc<dyn NoiseMechanism>>,
+    ) -> Result<Self, String> {
+        let mut registry = MechanismRegistry::default();
+        for mechanism in mechanisms {
+            registry.register_shared(mechanism)?;
+        }
+        Ok(registry)
+    }
+
+    /// Registers `mechanism` under its name.
+    ///
+    /// # Errors
+    /// Returns an error if a mechanism with the same name is already registered.
+    pub fn register(&mut self, mechanism: impl NoiseMechanism + 'static) -> Result<(), String> {
+        self.register_shared(Arc::new(mechanism))
+    }
+
+    /// Registers `mechanism` under its name, see `register`.
+    pub fn register_shared(&mut self, mechanism: Arc<dyn NoiseMechanism>) -> Result<(), String> {
+        let name = mechanism.name().to_string();
+        if self.mechanisms.contains_key(&name) {
+            return Err(format!(
+                "A noise mechanism named {name} is already registered"
+            ));
+        }
+        self.mechanisms.insert(name, mechanism);
+        Ok(())
+    }
+
+    /// Looks up the mechanism registered under `name`.
+    pub fn get(&self, name: &str) -> Result<Arc<dyn NoiseMechanism>, String> {
+        self.mechanisms
+            .get(name)
+            .cloned()
+            .ok_or(format!("Unknown noise mechanism: {name}"))
+    }
+
+    /// Names of all registered mechanisms, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.mechanisms.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+impl Default for MechanismRegistry {
+    fn default() -> Self {
+        let builtins: [Arc<dyn NoiseMechanism>; 3] = [
+            Arc::new(LaplaceMechanism),
+            Arc::new(GeometricMechanism),
+            Arc::new(GaussianMechanism),
+        ];
+        MechanismRegistry {
+            mechanisms: builtins
+                .into_iter()
+                .map(|mechanism| (mechanism.name().to_string(), mechanism))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MechanismRegistry, NoiseMechanism, DEFAULT_MECHANISM};
+    use crate::database::schema::PrivacyBudget;
+    use crate::transforms::gaussian_sigma;
+    use rand::RngCore;
+    use std::sync::Arc;
+
+    /// Adds nothing, a stand-in for a mechanism of another crate.
+    struct Constant(&'static str);
+
+    impl NoiseMechanism for Constant {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn privacy_cost(&self, epsilon: f64, delta: f64) -> Result<PrivacyBudget, String> {
+            Ok(PrivacyBudget::new(epsilon, delta))
+        }
+
+        fn sample(&self, true_value: f64, _: f64, _: &PrivacyBudget, _: &mut dyn RngCore) -> f64 {
+            true_value
+        }
+
+        fn scale(&self, _: f64, _: &PrivacyBudget) -> f64 {
+            0.0
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let registry = MechanismRegistry::default();
+        assert_eq!(
+            ["gaussian", "geometric", "laplace"].to_vec(),
+            registry.names()
+        );
+        assert_eq!("laplace", registry.get(DEFAULT_MECHANISM).unwrap().name());
+        assert_eq!(
+            Some("geometric"),
+            registry.get("laplace").unwrap().integer_counterpart()
+        );
+        assert!(registry.get("exponential").is_err());
+        assert!(MechanismRegistry::new().get("laplace").is_err());
+    }
+
+    #[test]
+    fn extension() {
+        let registry =
+            MechanismRegistry::with_builtins([Arc::new(Constant("custom")) as Arc<_>]).unwrap();
+        assert_eq!("custom", registry.get("custom").unwrap().name());
+        assert_eq!(4, registry.names().len());
+
+        // A built-in mechanism cannot be replaced, neither can one registered before.
+        assert!(
+            MechanismRegistry::with_builtins([Arc::new(Constant("laplace")) as Arc<_>]).is_err()
+        );
+        let mut registry = MechanismRegistry::new();
+        registry.register(Constant("custom")).unwrap();
+        assert!(registry.register(Constant("custom")).is_err());
+    }
+
+    #[test]
+    fn privacy_costs() {
+        let registry = MechanismRegistry::default();
+        for name in ["laplace", "geometric"] {
+            let mechanism = registry.get(name).unwrap();
+            // Pure epsilon-DP, the requested delta is not spent.
+            assert_eq!(
+                PrivacyBudget::new(2.0, 0.0),
+                mechanism.privacy_cost(2.0, 1e-5).unwrap()
+            );
+            assert_eq!(None, mechanism.zcdp_rho(&PrivacyBudget::new(2.0, 0.0)));
+            for epsilon in [0.0, -1.0, f64::INFINITY, f64::NAN] {
+                assert!(
+                    mechanism.privacy_cost(epsilon, 0.0).is_err(),
+                    "{name}: {epsilon}"
+                );
+            }
+        }
+
+        let gaussian = registry.get("gaussian").unwrap();
+        let cost = gaussian.privacy_cost(0.5, 1e-5).unwrap();
+        assert_eq!(PrivacyBudget::new(0.5, 1e-5), cost);
+        let sigma = gaussian_sigma(1.0, 0.5, 1e-5);
+        assert_eq!(Some(1.0 / (2.0 * sigma * sigma)), gaussian.zcdp_rho(&cost));
+        assert!(gaussian.privacy_cost(1.5, 1e-5).is_err());
+        assert!(gaussian.privacy_cost(0.5, 0.0).is_err());
+    }
+}
@@ -1,31 +1,131 @@
-use rand::Rng;
+pub mod discrete;
+pub mod elastic;
+pub mod mechanisms;
+pub mod moments;
+pub mod partition;
+pub mod quantile;
+pub mod rng;
+pub mod snapping;
 
-fn laplace_sample(location: f64, scale: f64) -> f64 {
-    let mut rng = rand::thread_rng();
-    let u: f64 = rng.gen_range(0.0..1.0); // Uniformly distributed variable in range (0, 1)
+use crate::logging::owner_diagnostic;
+use discrete::{gaussian_noise, geometric_noise};
+use rand::RngCore;
+use snapping::{snapping_granularity, snapping_scale, snapping_transform, SNAPPING_BOUND_FACTOR};
 
-    // Apply the inverse CDF method
-    let laplace_sample = if u < 0.5 {
-        location + scale * (u).ln() // Negative side
-    } else {
-        location - scale * (1.0 - u).ln() // Positive side
-    };
+/// The grid of the Gaussian noise is 2^-20 of its scale, see `add_gaussian_noise`.
+const GAUSSIAN_GRID_BITS: i32 = 20;
 
-    laplace_sample
+fn add_laplace_noise(
+    true_value: f64,
+    sensitivity: f64,
+    epsilon: f64,
+    rng: &mut dyn RngCore,
+) -> f64 {
+    // Laplace noise is sampled with the snapping mechanism, plain inverse CDF sampling on
+    // doubles leaks the true value through the low order bits.
+    let bound = SNAPPING_BOUND_FACTOR * sensitivity;
+    let noised_value = snapping_transform(true_value, sensitivity, epsilon, bound, rng);
+    owner_diagnostic(|| format!("Changing {true_value} to {noised_value} using {epsilon}"));
+    noised_value
 }
 
-fn add_laplace_noise(true_value: f64, sensitivity: f64, epsilon: f64) -> f64 {
-    let scale = sensitivity / epsilon;
-    let noise = laplace_sample(0.0, scale);
-    println!(
-        "Changing {} to {} using {}",
-        true_value,
-        true_value + noise,
-        epsilon
-    );
-    true_value + noise
+/// The scale of the Laplace noise added by `laplace_transform`, slightly above
+/// `sensitivity / epsilon` to account for the rounding of the snapping mechanism.
+pub fn laplace_scale(sensitivity: f64, epsilon: f64) -> f64 {
+    snapping_scale(sensitivity, epsilon, SNAPPING_BOUND_FACTOR * sensitivity)
 }
 
-pub fn laplace_transform(true_value: f64, sensitivity: f64, privacy_budget: f64) -> f64 {
-    add_laplace_noise(true_value, sensitivity, privacy_budget).round()
+pub fn laplace_transform(
+    true_value: f64,
+    sensitivity: f64,
+    privacy_budget: f64,
+    rng: &mut dyn RngCore,
+) -> f64 {
+    add_laplace_noise(true_value, sensitivity, privacy_budget, rng)
+}
+
+/// Two-sided geometric (discrete Laplace) mechanism, exactly epsilon-DP over the integers.
+///
+/// The sensitivity of an integer valued query is an integer, a fractional
+/// `sensitivity` is therefore rounded up. The noise is sampled exactly, see
+/// `discrete::geometric_noise`.
+///
+/// # Errors
+/// Returns an error unless `privacy_budget` is positive and finite.
+pub fn geometric_transform(
+    true_value: i64,
+    sensitivity: f64,
+    privacy_budget: f64,
+    rng: &mut dyn RngCore,
+) -> Result<i64, String> {
+    Ok(true_value.saturating_add(geometric_noise(privacy_budget, sensitivity, rng)?))
+}
+
+/// Standard deviation of the classic Gaussian mechanism,
+/// `sigma = sqrt(2 ln(1.25 / delta)) * l2_sensitivity / epsilon`.
+pub fn gaussian_sigma(l2_sensitivity: f64, epsilon: f64, delta: f64) -> f64 {
+    (2.0 * (1.25 / delta).ln()).sqrt() * l2_sensitivity / epsilon
+}
+
+/// Checks that `epsilon` is a budget pure epsilon-DP mechanisms can be calibrated for.
+pub fn validate_epsilon(epsilon: f64) -> Result<(), String> {
+    if !(epsilon > 0.0 && epsilon.is_finite()) {
+        return Err(format!(
+            "Epsilon must be positive and finite, got {epsilon}"
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that `epsilon` and `delta` are in the range the classic Gaussian mechanism is proven for.
+pub fn validate_gaussian_parameters(epsilon: f64, delta: f64) -> Result<(), String> {
+    if !(epsilon > 0.0 && epsilon < 1.0) {
+        return Err(format!(
+            "Gaussian mechanism needs 0 < epsilon < 1, got {epsilon}"
+        ));
+    }
+    if !(delta > 0.0 && delta < 1.0) {
+        return Err(format!(
+            "Gaussian mechanism needs 0 < delta < 1, got {delta}"
+        ));
+    }
+    Ok(())
+}
+
+fn add_gaussian_noise(
+    true_value: f64,
+    l2_sensitivity: f64,
+    epsilon: f64,
+    delta: f64,
+    rng: &mut dyn RngCore,
+) -> f64 {
+    // Gaussian noise sampled on doubles leaks the true value through the low order bits
+    // like Laplace noise does. The true value is rounded to a grid of a power of two
+    // instead and discrete Gaussian noise on that grid is added, sampled exactly.
+    // Rounding moves neighbouring values up to one step further apart, sigma covers
+    // that step on top of the sensitivity.
+    let sigma = gaussian_sigma(l2_sensitivity, epsilon, delta);
+    if sigma == 0.0 {
+        return true_value;
+    }
+    let granularity = snapping_granularity(sigma) * 2f64.powi(-GAUSSIAN_GRID_BITS);
+    // No noise on a grid of doubles covers an infinite sensitivity.
+    if !(sigma.is_finite() && granularity > 0.0) {
+        return f64::NAN;
+    }
+    let sigma = gaussian_sigma(l2_sensitivity + granularity, epsilon, delta);
+    let steps = (sigma / granularity).ceil() as u64;
+    // Scaling by a power of two is exact, the sum is rounded from an exact grid point.
+    let rounded = (true_value / granularity).round() * granularity;
+    rounded + gaussian_noise(steps, rng) as f64 * granularity
+}
+
+pub fn gaussian_transform(
+    true_value: f64,
+    l2_sensitivity: f64,
+    epsilon: f64,
+    delta: f64,
+    rng: &mut dyn RngCore,
+) -> f64 {
+    add_gaussian_noise(true_value, l2_sensitivity, epsilon, delta, rng)
 }
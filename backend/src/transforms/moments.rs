@@ -0,0 +1,152 @@
+use crate::database::schema::{ColumnBounds, PrivacyBudget};
+use crate::transforms::mechanisms::NoiseMechanism;
+use rand::RngCore;
+
+/*
+The sensitivity of an average depends on how many rows it is taken over, noising the true
+average cannot be calibrated without knowing n. AVG, VARIANCE and STDDEV are therefore
+answered from noised sums over values clamped into the bounds of their column.
+
+Values are centred on the midpoint m of the bounds, a clamped value then lies within the
+half width h of zero. Adding or removing a row changes the count by 1, the centred sum by
+at most h and the sum of centred squares by at most h^2. Each of these is noised with an
+equal share of the aggregate's budget, the average is m + sum / count, the variance
+squares / count - (sum / count)^2 and the standard deviation its root. The noised count
+is floored at 1 and the results are clamped into what the bounds allow.
+*/
+
+/// An aggregate answered from noised count, sum and sum of squares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Moment {
+    Mean,
+    Variance,
+    StandardDeviation,
+}
+
+impl Moment {
+    /// The moment the lowercased aggregate `function` computes, if any.
+    pub fn of(function: &str) -> Option<Moment> {
+        match function {
+            "avg" => Some(Moment::Mean),
+            "variance" => Some(Moment::Variance),
+            "stddev" => Some(Moment::StandardDeviation),
+            _ => None,
+        }
+    }
+
+    /// How many sums are noised, each at an equal share of the budget.
+    pub fn components(&self) -> usize {
+        match self {
+            Moment::Mean => 2,
+            Moment::Variance | Moment::StandardDeviation => 3,
+        }
+    }
+}
+
+/// The true sums a moment is released from, over values clamped into the bounds and
+/// centred on their midpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sums {
+    pub count: f64,
+    pub sum: f64,
+    pub squares: f64,
+}
+
+/// The midpoint values are centred on before they are summed.
+pub fn midpoint(bounds: &ColumnBounds) -> f64 {
+    (bounds.lower + bounds.upper) / 2.0
+}
+
+/// The largest distance of a clamped value from the midpoint.
+pub fn half_width(bounds: &ColumnBounds) -> f64 {
+    (bounds.upper - bounds.lower) / 2.0
+}
+
+/// Releases `moment` from noised `sums`.
+///
+/// # Parameters
+/// - `moment`: The aggregate to release.
+/// - `sums`: The true sums over the clamped and centred values.
+/// - `bounds`: The bounds the values were clamped into.
+/// - `contributions`: How many of the values a single privacy unit may contribute.
+/// - `mechanism`: The mechanism every sum is noised with.
+/// - `cost`: The (epsilon, delta) spent on each sum.
+/// - `rng`: The source of randomness for the noise.
+///
+/// # Returns
+/// The noised moment, within the bounds for an average and within [0, h^2] for a variance.
+pub fn noised_moment(
+    moment: Moment,
+    sums: &Sums,
+    bounds: &ColumnBounds,
+    contributions: f64,
+    mechanism: &dyn NoiseMechanism,
+    cost: &PrivacyBudget,
+    rng: &mut dyn RngCore,
+) -> f64 {
+    let half_width = half_width(bounds);
+    let count = mechanism
+        .sample(sums.count, contributions, cost, rng)
+        .max(1.0);
+    let mean = (mechanism.sample(sums.sum, half_width * contributions, cost, rng) / count)
+        .clamp(-half_width, half_width);
+    if moment == Moment::Mean {
+        return midpoint(bounds) + mean;
+    }
+    let squares_sensitivity = half_width * half_width * contributions;
+    let squares = mechanism.sample(sums.squares, squares_sensitivity, cost, rng) / count;
+    let variance = (squares - mean * mean).clamp(0.0, half_width * half_width);
+    match moment {
+        Moment::StandardDeviation => variance.sqrt(),
+        _ => variance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{midpoint, noised_moment, Moment, Sums};
+    use crate::database::schema::{ColumnBounds, PrivacyBudget};
+    use crate::transforms::mechanisms::LaplaceMechanism;
+    use crate::transforms::rng::NoiseRng;
+
+    #[test]
+    fn moments() {
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let bounds = ColumnBounds::new(0.0, 100.0).unwrap();
+        // 1000 values, half of them 40 and half 60.
+        let values = [40.0, 60.0].repeat(500);
+        let centred = values.iter().map(|value| value - midpoint(&bounds));
+        let sums = Sums {
+            count: values.len() as f64,
+            sum: centred.clone().sum(),
+            squares: centred.map(|value| value * value).sum(),
+        };
+        let cost = PrivacyBudget::new(10.0, 0.0);
+        for (moment, truth) in [
+            (Moment::Mean, 50.0),
+            (Moment::Variance, 100.0),
+            (Moment::StandardDeviation, 10.0),
+        ] {
+            let released = noised_moment(
+                moment,
+                &sums,
+                &bounds,
+                1.0,
+                &LaplaceMechanism,
+                &cost,
+                &mut rng,
+            );
+            assert!((released - truth).abs() < 5.0, "{moment:?}: {released}");
+        }
+        let empty = noised_moment(
+            Moment::Variance,
+            &Sums::default(),
+            &bounds,
+            1.0,
+            &LaplaceMechanism,
+            &cost,
+            &mut rng,
+        );
+        assert!((0.0..=2500.0).contains(&empty));
+    }
+}
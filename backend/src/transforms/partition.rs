@@ -0,0 +1,64 @@
+use crate::transforms::laplace_transform;
+use rand::RngCore;
+
+/*
+Releasing the groups of a GROUP BY leaks on its own, a group that only exists because of
+a single row tells that this row is in the table. Groups are therefore released only if
+their noised size clears a threshold (Laplace thresholding, Korolova et al. 2009).
+
+Every row belongs to exactly one group, adding or removing a row changes the size of a
+single group by one. Noising the sizes with Laplace(1 / epsilon) and dropping every group
+below 1 + ln(1 / (2 delta)) / epsilon is (epsilon, delta)-DP, delta bounds the chance of
+releasing a group of size one.
+*/
+
+/// Checks that partition selection can be calibrated for `epsilon` and `delta`.
+///
+/// # Errors
+/// Returns an error unless `epsilon > 0` and `0 < delta < 1`.
+pub fn validate_partition_parameters(epsilon: f64, delta: f64) -> Result<(), String> {
+    if epsilon <= 0.0 {
+        return Err("Partition selection needs a positive epsilon".to_string());
+    }
+    if delta <= 0.0 || delta >= 1.0 {
+        return Err("Partition selection needs a delta between 0 and 1 (exclusive)".to_string());
+    }
+    Ok(())
+}
+
+/// The noised size a group needs to reach to be released.
+pub fn partition_threshold(epsilon: f64, delta: f64) -> f64 {
+    1.0 + (1.0 / (2.0 * delta)).ln() / epsilon
+}
+
+/// Whether a group of `size` rows is released.
+///
+/// # Parameters
+/// - `size`: The true number of rows in the group.
+/// - `epsilon`: The epsilon spent on the selection.
+/// - `delta`: The probability with which a group of a single row may be released.
+/// - `rng`: The source of randomness for the noise.
+pub fn keep_partition(size: f64, epsilon: f64, delta: f64, rng: &mut dyn RngCore) -> bool {
+    laplace_transform(size, 1.0, epsilon, rng) >= partition_threshold(epsilon, delta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{keep_partition, partition_threshold, validate_partition_parameters};
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn parameters() {
+        assert!(validate_partition_parameters(1.0, 1e-5).is_ok());
+        assert!(validate_partition_parameters(1.0, 0.0).is_err());
+        assert!(validate_partition_parameters(0.0, 1e-5).is_err());
+    }
+
+    #[test]
+    fn threshold() {
+        let threshold = partition_threshold(1.0, 1e-5);
+        assert!(threshold > 11.0 && threshold < 12.0);
+        assert!(keep_partition(1e6, 1.0, 1e-5, &mut OsRng));
+        assert!(!keep_partition(1.0, 1.0, 1e-9, &mut OsRng));
+    }
+}
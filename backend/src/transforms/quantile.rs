@@ -0,0 +1,123 @@
+use crate::database::schema::ColumnBounds;
+use rand::{Rng, RngCore};
+
+/*
+Noising a MIN, MAX or MEDIAN with Laplace noise needs the sensitivity of the extreme,
+which is the whole width of the column: a single row can move the maximum from the lower
+to the upper bound. Quantiles are therefore released with the exponential mechanism
+(Smith 2011) over the bounds of the column.
+
+The n values, clamped into [lower, upper], split the bounds into n + 1 intervals. Every
+point of the i-th interval has i values below it and scores -|i - p n|, the interval is
+drawn with probability proportional to its width times exp(epsilon score / 2) and the
+released value uniformly inside it. Adding or removing a row changes the score of any
+point by at most max(p, 1 - p) <= 1, the release is epsilon-DP. MIN and MAX are the 0th
+and the 1st quantile.
+*/
+
+/// Checks that `quantile` is in [0, 1].
+///
+/// # Errors
+/// Returns an error naming `quantile` otherwise.
+pub fn validate_quantile(quantile: f64) -> Result<(), String> {
+    if !(0.0..=1.0).contains(&quantile) {
+        return Err(format!(
+            "Percentiles must be between 0 and 1, got {quantile}"
+        ));
+    }
+    Ok(())
+}
+
+/// The temperature of the exponential mechanism, `2 / epsilon` for scores of sensitivity one.
+pub fn quantile_scale(epsilon: f64) -> f64 {
+    2.0 / epsilon
+}
+
+/// Releases the `quantile` of `values` with the exponential mechanism.
+///
+/// # Parameters
+/// - `values`: The true values, in any order, they are clamped into `bounds`.
+/// - `quantile`: The quantile to release, 0 for the minimum and 1 for the maximum.
+/// - `bounds`: The range the data owner declared for the column.
+/// - `epsilon`: The epsilon spent on the release.
+/// - `rng`: The source of randomness for the sampling.
+///
+/// # Returns
+/// A value within `bounds`, uniformly distributed over them if `values` is empty.
+pub fn exponential_quantile(
+    values: &[f64],
+    quantile: f64,
+    bounds: &ColumnBounds,
+    epsilon: f64,
+    rng: &mut dyn RngCore,
+) -> f64 {
+    let mut points: Vec<f64> = values.iter().map(|value| bounds.clamp(*value)).collect();
+    points.sort_by(f64::total_cmp);
+    points.insert(0, bounds.lower);
+    points.push(bounds.upper);
+    let target = quantile * values.len() as f64;
+
+    // Weights are kept as logarithms, exp(epsilon score / 2) underflows on large tables.
+    let log_weights: Vec<f64> = points
+        .windows(2)
+        .enumerate()
+        .map(|(below, interval)| {
+            let width = interval[1] - interval[0];
+            width.ln() - epsilon * (below as f64 - target).abs() / 2.0
+        })
+        .collect();
+    let max_log_weight = log_weights
+        .iter()
+        .copied()
+        .fold(f64::NEG_INFINITY, f64::max);
+    let weights: Vec<f64> = log_weights
+        .iter()
+        .map(|log_weight| (log_weight - max_log_weight).exp())
+        .collect();
+
+    let mut threshold = rng.gen_range(0.0..1.0) * weights.iter().sum::<f64>();
+    // Empty intervals have no weight, the last interval with weight is the fallback for rounding.
+    let mut chosen = weights
+        .iter()
+        .rposition(|weight| *weight > 0.0)
+        .unwrap_or(0);
+    for (index, weight) in weights.iter().enumerate() {
+        if *weight > 0.0 && threshold < *weight {
+            chosen = index;
+            break;
+        }
+        threshold -= weight;
+    }
+    let (lower, upper) = (points[chosen], points[chosen + 1]);
+    lower + rng.gen_range(0.0..1.0) * (upper - lower)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exponential_quantile, validate_quantile};
+    use crate::database::schema::ColumnBounds;
+    use crate::transforms::rng::NoiseRng;
+
+    #[test]
+    fn parameters() {
+        assert!(validate_quantile(0.5).is_ok());
+        assert!(validate_quantile(1.0).is_ok());
+        assert!(validate_quantile(50.0).is_err());
+    }
+
+    #[test]
+    fn quantiles_land_near_the_truth() {
+        let mut rng = NoiseRng::seeded(7).unwrap();
+        let bounds = ColumnBounds::new(0.0, 1000.0).unwrap();
+        let values: Vec<f64> = (1..=1000).map(f64::from).collect();
+        for (quantile, truth) in [(0.0, 1.0), (0.5, 500.0), (0.9, 900.0), (1.0, 1000.0)] {
+            let released = exponential_quantile(&values, quantile, &bounds, 1.0, &mut rng);
+            assert!((released - truth).abs() < 25.0, "{quantile}: {released}");
+        }
+        // Outliers are clamped, nothing is released outside of the bounds.
+        let released = exponential_quantile(&[1e9], 1.0, &bounds, 1.0, &mut rng);
+        assert!((0.0..=1000.0).contains(&released));
+        let released = exponential_quantile(&[], 0.5, &bounds, 1.0, &mut rng);
+        assert!((0.0..=1000.0).contains(&released));
+    }
+}
@@ -0,0 +1,129 @@
+use rand::rngs::{OsRng, StdRng};
+use rand::{RngCore, SeedableRng};
+
+/// Environment variable holding the seed for a reproducible `NoiseRng`, debug builds only.
+pub const SEED_VARIABLE: &str = "DIFFPRIV_SEED";
+
+/// The source of randomness for every noise mechanism.
+///
+/// Production builds always draw from the operating system's CSPRNG, a seeded
+/// generator makes the noise predictable and is only available in debug and
+/// test builds so that noised outputs can be asserted on.
+pub enum NoiseRng {
+    Secure(OsRng),
+    Seeded(Box<StdRng>),
+}
+
+impl NoiseRng {
+    /// A generator backed by the operating system's CSPRNG.
+    pub fn secure() -> Self {
+        NoiseRng::Secure(OsRng)
+    }
+
+    /// A reproducible generator, every generator created from the same `seed` yields the same noise.
+    ///
+    /// # Errors
+    /// Returns an error in release builds, where noise must never be predictable.
+    pub fn seeded(seed: u64) -> Result<Self, String> {
+        NoiseRng::seeded_if(cfg!(debug_assertions), seed)
+    }
+
+    fn seeded_if(debug_build: bool, seed: u64) -> Result<Self, String> {
+        if debug_build {
+            Ok(NoiseRng::Seeded(Box::new(StdRng::seed_from_u64(seed))))
+        } else {
+            Err("Refusing to use a seeded RNG in a release build!".to_string())
+        }
+    }
+
+    /// A seeded generator if `DIFFPRIV_SEED` is set, otherwise a secure one.
+    ///
+    /// # Errors
+    /// Returns an error if the seed is not a `u64` or if this is a release build.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var(SEED_VARIABLE) {
+            Ok(seed) => {
+                let seed = seed
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("{SEED_VARIABLE} must be an unsigned integer"))?;
+                NoiseRng::seeded(seed)
+            }
+            Err(_) => Ok(NoiseRng::secure()),
+        }
+    }
+
+    pub fn is_seeded(&self) -> bool {
+        matches!(self, NoiseRng::Seeded(_))
+    }
+}
+
+impl RngCore for NoiseRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            NoiseRng::Secure(rng) => rng.next_u32(),
+            NoiseRng::Seeded(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            NoiseRng::Secure(rng) => rng.next_u64(),
+            NoiseRng::Seeded(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            NoiseRng::Secure(rng) => rng.fill_bytes(dest),
+            NoiseRng::Seeded(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            NoiseRng::Secure(rng) => rng.try_fill_bytes(dest),
+            NoiseRng::Seeded(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NoiseRng, SEED_VARIABLE};
+    use rand::RngCore;
+
+    fn draws(rng: &mut NoiseRng) -> Vec<u64> {
+        (0..4).map(|_| rng.next_u64()).collect()
+    }
+
+    #[test]
+    fn seeded_is_reproducible() {
+        let mut first = NoiseRng::seeded(7).unwrap();
+        assert!(first.is_seeded());
+        assert_eq!(draws(&mut first), draws(&mut NoiseRng::seeded(7).unwrap()));
+        assert_ne!(draws(&mut first), draws(&mut NoiseRng::seeded(8).unwrap()));
+        assert!(!NoiseRng::secure().is_seeded());
+    }
+
+    #[test]
+    fn seeded_is_refused_in_release_builds() {
+        assert!(NoiseRng::seeded_if(false, 7).is_err());
+        assert_eq!(cfg!(debug_assertions), NoiseRng::seeded(7).is_ok());
+    }
+
+    // The only test touching the variable, tests run in parallel in the same process.
+    #[test]
+    fn from_env() {
+        std::env::remove_var(SEED_VARIABLE);
+        assert!(!NoiseRng::from_env().unwrap().is_seeded());
+
+        std::env::set_var(SEED_VARIABLE, " 7 ");
+        let mut rng = NoiseRng::from_env().unwrap();
+        assert_eq!(draws(&mut rng), draws(&mut NoiseRng::seeded(7).unwrap()));
+
+        std::env::set_var(SEED_VARIABLE, "-1");
+        assert!(NoiseRng::from_env().is_err());
+        std::env::remove_var(SEED_VARIABLE);
+    }
+}
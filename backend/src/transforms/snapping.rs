@@ -0,0 +1,140 @@
+/*
+The snapping mechanism (Mironov, "On Significance of the Least Significant Bits
+For Differential Privacy", CCS 2012).
+
+Textbook Laplace sampling, `x + scale * ln(u)` on doubles, leaks `x` through the
+low order bits of the output since not every double is reachable from every `x`.
+The snapping mechanism clamps the input to [-B, B], samples `ln(u)` from a
+uniform `u` drawn with full precision and rounds the result to a multiple of a
+power of two `Λ >= scale`, so that the set of possible outputs no longer depends
+on the true value.
+*/
+use rand::RngCore;
+
+/// Default clamping bound in multiples of the sensitivity, used when the caller has no
+/// data-independent bound on the true value.
+pub const SNAPPING_BOUND_FACTOR: f64 = (1u64 << 40) as f64;
+
+/// Mironov's bound on the privacy lost to floating point error, `2^-49 * B / λ`.
+const ROUNDING_ERROR_FACTOR: f64 = 1.0 / (1u64 << 49) as f64;
+
+/// Samples a uniform double in (0, 1) where every double can come up, each with a
+/// probability proportional to the gap to its neighbour.
+fn uniform_full_precision(rng: &mut dyn RngCore) -> f64 {
+    // The binade [2^-(k + 1), 2^-k) is picked with probability 2^-(k + 1),
+    // that is one more than the number of leading zeros in a stream of random bits.
+    let mut exponent: i32 = -1;
+    loop {
+        let bits = rng.next_u64();
+        if bits != 0 {
+            exponent -= bits.leading_zeros() as i32;
+            break;
+        }
+        exponent -= 64;
+        if exponent < -1022 {
+            return f64::MIN_POSITIVE;
+        }
+    }
+    let mantissa = rng.next_u64() >> 12; // 52 bits
+    let significand = 1.0 + mantissa as f64 / (1u64 << 52) as f64;
+    // exponent >= -1022 here, so the result is a normal double and exact.
+    significand * 2f64.powi(exponent)
+}
+
+/// The smallest power of two greater than or equal to `value`.
+pub fn snapping_granularity(value: f64) -> f64 {
+    let mut granularity = 2f64.powi(value.log2().ceil() as i32);
+    // log2 may be off by one ulp around exact powers of two.
+    if granularity < value {
+        granularity *= 2.0;
+    } else if granularity / 2.0 >= value {
+        granularity /= 2.0;
+    }
+    granularity
+}
+
+/// Laplace scale for the snapping mechanism so that the release is `epsilon`-DP,
+/// the floating point slack `2^-49 * B` is paid for by a slightly larger scale.
+pub fn snapping_scale(sensitivity: f64, epsilon: f64, bound: f64) -> f64 {
+    (sensitivity + ROUNDING_ERROR_FACTOR * bound) / epsilon
+}
+
+/// Releases `true_value` with the snapping mechanism.
+///
+/// # Parameters
+/// - `true_value`: The value to be noised.
+/// - `sensitivity`: The L1 sensitivity of the query.
+/// - `epsilon`: The privacy budget spent on the release.
+/// - `bound`: A data-independent bound B, the input and output are clamped to [-B, B].
+/// - `rng`: The source of randomness, see `transforms::rng::NoiseRng`.
+///
+/// # Returns
+/// A multiple of the smallest power of two greater than the Laplace scale, within [-B, B].
+pub fn snapping_transform(
+    true_value: f64,
+    sensitivity: f64,
+    epsilon: f64,
+    bound: f64,
+    rng: &mut dyn RngCore,
+) -> f64 {
+    if sensitivity == 0.0 {
+        // Nothing is calibrated to a zero sensitivity, the value is released as is.
+        return true_value;
+    }
+    let scale = snapping_scale(sensitivity, epsilon, bound);
+    let granularity = snapping_granularity(scale);
+    let sign = if rng.next_u32() & 1 == 0 { 1.0 } else { -1.0 };
+    let noised = true_value.clamp(-bound, bound) + sign * scale * uniform_full_precision(rng).ln();
+    // Division and multiplication by a power of two are exact.
+    let snapped = (noised / granularity).round() * granularity;
+    snapped.clamp(-bound, bound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        snapping_granularity, snapping_scale, snapping_transform, uniform_full_precision,
+        SNAPPING_BOUND_FACTOR,
+    };
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn granularity_is_power_of_two() {
+        assert_eq!(1.0, snapping_granularity(1.0));
+        assert_eq!(2.0, snapping_granularity(1.5));
+        assert_eq!(0.125, snapping_granularity(0.1));
+        assert_eq!(1024.0, snapping_granularity(1000.0));
+    }
+
+    #[test]
+    fn uniform_in_unit_interval() {
+        for _ in 0..10_000 {
+            let u = uniform_full_precision(&mut OsRng);
+            assert!(u > 0.0 && u < 1.0);
+        }
+    }
+
+    #[test]
+    fn output_on_lattice() {
+        let (sensitivity, epsilon) = (1.0, 0.3);
+        let bound = SNAPPING_BOUND_FACTOR * sensitivity;
+        let granularity = snapping_granularity(snapping_scale(sensitivity, epsilon, bound));
+        for true_value in [0.0, 1.0, 42.7, -13.3, 1e6 + 0.1] {
+            for _ in 0..1_000 {
+                let noised =
+                    snapping_transform(true_value, sensitivity, epsilon, bound, &mut OsRng);
+                assert_eq!(0.0, (noised / granularity).fract());
+                assert!(noised.abs() <= bound);
+            }
+        }
+    }
+
+    #[test]
+    fn output_clamped_to_bound() {
+        for _ in 0..1_000 {
+            let noised = snapping_transform(1e9, 1.0, 1.0, 100.0, &mut OsRng);
+            assert!(noised.abs() <= 100.0);
+            assert_eq!(0.0, noised.fract());
+        }
+    }
+}
@@ -1,13 +1,44 @@
-import { useState } from "react";
+import { useEffect, useState } from "react";
 import { invoke } from "@tauri-apps/api";
 import { toast } from "sonner";
 import "../styles/Execution.css";
 
+const formatValue = (value) => {
+  if (typeof value === "string") {
+    return value;
+  }
+  const scale = value.scale === null ? "scale hidden" : `scale ${value.scale.toPrecision(3)}`;
+  return `${value.value} (${value.mechanism}, ${scale}, ε ${value.epsilon})`;
+};
+
+const formatResultSet = ({ columns, rows }) => {
+  const header = columns.map((column) => column.label).join(" | ");
+  const lines = rows.map((row) => row.map(formatValue).join(" | "));
+  return [header, ...lines].join("\n");
+};
+
+const formatSpent = (spent) =>
+  Object.entries(spent)
+    .map(([table, { epsilon, delta }]) => `${table}: ε ${epsilon.toPrecision(3)}, δ ${delta}`)
+    .join(", ");
+
 const ExecutionWindow = () => {
+  const [analyst, setAnalyst] = useState("");
   const [input, setInput] = useState("");
   const [budget, setBudget] = useState("");
+  const [mechanisms, setMechanisms] = useState(["laplace"]);
+  const [mechanism, setMechanism] = useState("laplace");
+  const [delta, setDelta] = useState("");
+  const [allocation, setAllocation] = useState("even");
+  const [weights, setWeights] = useState("");
   const [output, setOutput] = useState([]);
 
+  useEffect(() => {
+    invoke("get_mechanisms")
+      .then(setMechanisms)
+      .catch((err) => console.log(err));
+  }, []);
+
   const handleInputChange = (e) => {
     setInput(e.target.value);
   };
@@ -17,6 +48,10 @@ const ExecutionWindow = () => {
   };
 
   const handleExecute = async () => {
+    if (!analyst) {
+      toast.error("Provide the analyst running the query!", { duration: 2000 });
+      return;
+    }
     if (!budget) {
       toast.error("Provide the budget for the query!", { duration: 2000 });
       return;
@@ -24,11 +59,19 @@ const ExecutionWindow = () => {
 
     try {
       let result = await invoke("execute_sql", {
+        analyst,
         query: input,
         budget: parseFloat(budget),
+        mechanism,
+        delta: delta ? parseFloat(delta) : null,
+        allocation:
+          allocation === "weighted"
+            ? { kind: allocation, weights: weights.split(",").map(parseFloat) }
+            : { kind: allocation },
       });
 
-      const newOutput = `${input}\n> ${JSON.stringify(result, null)}`;
+      const spent = await invoke("get_privacy_spent");
+      const newOutput = `${input}\n${formatResultSet(result)}\nSpent so far: ${formatSpent(spent)}`;
       setOutput([...output, newOutput]);
       setInput("");
       setBudget("");
@@ -37,6 +80,19 @@ const ExecutionWindow = () => {
     }
   };
 
+  const handleExport = async (format) => {
+    try {
+      const exported = await invoke("export_audit_log", { format });
+      const link = document.createElement("a");
+      link.href = URL.createObjectURL(new Blob([exported], { type: "text/plain" }));
+      link.download = `audit-log.${format}`;
+      link.click();
+      URL.revokeObjectURL(link.href);
+    } catch (err) {
+      toast.error(err, { duration: 2000 });
+    }
+  };
+
   return (
     <div className="exc-window">
       <div className="output-window">
@@ -55,6 +111,13 @@ const ExecutionWindow = () => {
           placeholder="Enter SQL..."
         />
         <div className="button-and-input">
+          <input
+            type="text"
+            value={analyst}
+            onChange={(e) => setAnalyst(e.target.value)}
+            className="input-field second"
+            placeholder="Analyst..."
+          />
           <input
             type="text"
             value={budget}
@@ -62,10 +125,52 @@ const ExecutionWindow = () => {
             className="input-field second"
             placeholder="Enter budget..."
           />
+          <select
+            value={mechanism}
+            onChange={(e) => setMechanism(e.target.value)}
+            className="input-field second"
+          >
+            {mechanisms.map((name) => (
+              <option key={name} value={name}>
+                {name}
+              </option>
+            ))}
+          </select>
+          <input
+            type="text"
+            value={delta}
+            onChange={(e) => setDelta(e.target.value)}
+            className="input-field second"
+            placeholder="Enter delta (optional)..."
+          />
+          <select
+            value={allocation}
+            onChange={(e) => setAllocation(e.target.value)}
+            className="input-field second"
+          >
+            <option value="even">Split evenly</option>
+            <option value="weighted">Split by weight</option>
+            <option value="per_aggregate">Per aggregate</option>
+          </select>
+          {allocation === "weighted" && (
+            <input
+              type="text"
+              value={weights}
+              onChange={(e) => setWeights(e.target.value)}
+              className="input-field second"
+              placeholder="Weights, e.g. 1, 2..."
+            />
+          )}
         </div>
         <button onClick={handleExecute} className="execute-button">
           Execute
         </button>
+        <button onClick={() => handleExport("csv")} className="execute-button">
+          Export audit log (CSV)
+        </button>
+        <button onClick={() => handleExport("json")} className="execute-button">
+          Export audit log (JSON)
+        </button>
       </div>
     </div>
   );
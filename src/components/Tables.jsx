@@ -7,6 +7,7 @@ const Tables = ({ onSet }) => {
   const [tables, setTables] = useState([]);
   const [inputValues, setInputValues] = useState({});
   const [tableBudgets, setTableBudgets] = useState({});
+  const [tableDeltas, setTableDeltas] = useState({});
 
   const get_tables = async () => {
     try {
@@ -19,7 +20,10 @@ const Tables = ({ onSet }) => {
       tables.forEach((table) => {
         initialInputValues[table.name] = {};
         table.columns.forEach((column) => {
-          initialInputValues[table.name][column.name] = "";
+          // Sensitivities loaded from conf.json are kept unless changed.
+          initialInputValues[table.name][column.name] = column.sensitivity
+            ? String(column.sensitivity)
+            : "";
         });
       });
       setInputValues(initialInputValues);
@@ -49,6 +53,13 @@ const Tables = ({ onSet }) => {
     }));
   };
 
+  const handleDeltaChange = (tableName, delta) => {
+    setTableDeltas((prevDelta) => ({
+      ...prevDelta,
+      [tableName]: delta,
+    }));
+  };
+
   const handleSensitivityInput = async () => {
     const convertedValues = {};
     const convertedBudgetValues = {};
@@ -59,9 +70,17 @@ const Tables = ({ onSet }) => {
         convertedValues[table][column] = value === "" ? 0.0 : parseFloat(value);
       }
     }
-    for (let table in tableBudgets) {
-      convertedBudgetValues[table] =
-        tableBudgets[table] === "" ? 0.0 : parseFloat(tableBudgets[table]);
+    for (let table of tables.map((table) => table.name)) {
+      const epsilon = tableBudgets[table];
+      const delta = tableDeltas[table];
+      // Tables without a new budget keep theirs, e.g. the one from conf.json.
+      if (!epsilon && !delta) {
+        continue;
+      }
+      convertedBudgetValues[table] = {
+        epsilon: !epsilon ? 0.0 : parseFloat(epsilon),
+        delta: !delta ? 0.0 : parseFloat(delta),
+      };
     }
     try {
       let sensitivity_msg = await invoke("set_sensitivities", {
@@ -108,6 +127,15 @@ const Tables = ({ onSet }) => {
                   }
                 />
               </div>
+
+              <div className="table-column">
+                <input
+                  type="text"
+                  placeholder="Allowed Delta"
+                  value={tableDeltas[table.name] || ""}
+                  onChange={(e) => handleDeltaChange(table.name, e.target.value)}
+                />
+              </div>
             </div>
           </div>
         ))}